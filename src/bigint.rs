@@ -0,0 +1,520 @@
+use std::cmp::Ordering;
+
+/// Results with more bits than this are refused with `Overflow` rather than
+/// letting `2^2^40` eat all memory.
+pub const MAX_BITS: u64 = 1 << 20;
+
+/// An arbitrary-precision integer.
+///
+/// The VM keeps integers as `i64` and only switches to a `BigInt` when a
+/// result no longer fits, so most programs never allocate one.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct BigInt {
+    neg: bool,
+    mag: Vec<u32>, // magnitude, least significant limb first, no trailing zeros
+}
+
+impl BigInt {
+    fn from_mag(neg: bool, mut mag: Vec<u32>) -> BigInt {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        let neg = neg && !mag.is_empty();
+        return BigInt { neg, mag };
+    }
+
+    pub fn is_zero(&self) -> bool {
+        return self.mag.is_empty();
+    }
+
+    pub fn is_negative(&self) -> bool {
+        return self.neg;
+    }
+
+    /// Number of bits in the magnitude; 0 for zero.
+    pub fn bits(&self) -> u64 {
+        match self.mag.last() {
+            Some(top) => (self.mag.len() as u64 - 1) * 32 + (32 - top.leading_zeros()) as u64,
+            None => 0,
+        }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let m = self.mag.iter().rev().fold(0u64, |acc, &l| (acc << 32) | l as u64);
+        if self.neg {
+            return 0i64.checked_sub_unsigned(m);
+        }
+        return i64::try_from(m).ok();
+    }
+
+    /// The nearest `f64`, or an infinity when out of range.
+    pub fn to_f64(&self) -> f64 {
+        let bits = self.bits();
+        let f = if bits <= 64 {
+            self.mag.iter().rev().fold(0u64, |acc, &l| (acc << 32) | l as u64) as f64
+        } else {
+            // Keep the top 64 bits and fold everything below into a sticky
+            // bit, which is enough for the conversion to round correctly.
+            let shift = bits - 64;
+            let top = shr_mag(&self.mag, shift as usize);
+            let mut t = top.iter().rev().fold(0u64, |acc, &l| (acc << 32) | l as u64);
+            if shl_mag(&top, shift as usize) != self.mag {
+                t |= 1;
+            }
+            (t as f64) * 2f64.powi(shift.min(2000) as i32)
+        };
+        return if self.neg { -f } else { f };
+    }
+
+    /// The integer part of `f`, or None for NaN and infinities.
+    pub fn from_f64(f: f64) -> Option<BigInt> {
+        if !f.is_finite() {
+            return None;
+        }
+        let bits = f.to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as i64;
+        if exp < 1023 {
+            return Some(BigInt::default()); // |f| < 1
+        }
+        let mant = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let m = vec![mant as u32, (mant >> 32) as u32];
+        let e = exp - 1075;
+        let mag = if e >= 0 { shl_mag(&m, e as usize) } else { shr_mag(&m, (-e) as usize) };
+        return Some(BigInt::from_mag(f < 0.0, mag));
+    }
+
+    /// Parses unsigned digits in the given radix; no sign, prefix or `_`.
+    pub fn parse(digits: &str, radix: u32) -> Option<BigInt> {
+        if digits.is_empty() {
+            return None;
+        }
+        let mut mag: Vec<u32> = Vec::new();
+        for c in digits.chars() {
+            let d = c.to_digit(radix)?;
+            mul_small(&mut mag, radix);
+            add_small(&mut mag, d);
+        }
+        return Some(BigInt::from_mag(false, mag));
+    }
+
+    pub fn abs(&self) -> BigInt {
+        return BigInt { neg: false, mag: self.mag.clone() };
+    }
+
+    /// Quotient and remainder rounded towards zero, like `/` and `%` on i64.
+    /// Panics if `d` is zero.
+    pub fn div_rem(&self, d: &BigInt) -> (BigInt, BigInt) {
+        let (q, r) = div_rem_mag(&self.mag, &d.mag);
+        return (BigInt::from_mag(self.neg != d.neg, q), BigInt::from_mag(self.neg, r));
+    }
+
+    /// Quotient and remainder rounded towards negative infinity, so the
+    /// remainder takes the sign of the divisor. Panics if `d` is zero.
+    pub fn div_mod_floor(&self, d: &BigInt) -> (BigInt, BigInt) {
+        let (q, r) = self.div_rem(d);
+        if !r.is_zero() && r.neg != d.neg {
+            return (&q - &BigInt::from(1), &r + d);
+        }
+        return (q, r);
+    }
+
+    pub fn pow(&self, mut e: u32) -> BigInt {
+        let mut base = self.clone();
+        let mut r = BigInt::from(1);
+        while e > 0 {
+            if e & 1 == 1 {
+                r = &r * &base;
+            }
+            e >>= 1;
+            if e > 0 {
+                base = &base * &base;
+            }
+        }
+        return r;
+    }
+
+    /// The largest integer whose square does not exceed `self`, which must
+    /// not be negative.
+    pub fn isqrt(&self) -> BigInt {
+        if self.is_zero() {
+            return BigInt::default();
+        }
+        // Newton's iteration from a power of two above the root.
+        let mut x = BigInt::from_mag(false, shl_mag(&[1], self.bits().div_ceil(2) as usize));
+        loop {
+            let y = &(&x + &(self.div_rem(&x).0)) >> 1;
+            if y >= x {
+                return x;
+            }
+            x = y;
+        }
+    }
+
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let r = a.div_rem(&b).1;
+            a = b;
+            b = r;
+        }
+        return a;
+    }
+
+    // Two's complement limbs, sign-extended to `len`.
+    fn twos(&self, len: usize) -> Vec<u32> {
+        let mut v = self.mag.clone();
+        v.resize(len, 0);
+        if self.neg {
+            for l in v.iter_mut() {
+                *l = !*l;
+            }
+            add_small(&mut v, 1);
+            v.truncate(len);
+        }
+        return v;
+    }
+
+    fn from_twos(mut v: Vec<u32>) -> BigInt {
+        let neg = v.last().is_some_and(|&top| top >> 31 == 1);
+        if neg {
+            for l in v.iter_mut() {
+                *l = !*l;
+            }
+            add_small(&mut v, 1);
+        }
+        return BigInt::from_mag(neg, v);
+    }
+
+    fn bitwise(&self, other: &BigInt, f: fn(u32, u32) -> u32) -> BigInt {
+        let len = self.mag.len().max(other.mag.len()) + 1;
+        let (a, b) = (self.twos(len), other.twos(len));
+        return BigInt::from_twos(a.iter().zip(&b).map(|(&x, &y)| f(x, y)).collect());
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(i: i64) -> BigInt {
+        let m = i.unsigned_abs();
+        return BigInt::from_mag(i < 0, vec![m as u32, (m >> 32) as u32]);
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl std::fmt::Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Peel off nine decimal digits at a time.
+        let mut chunks: Vec<u32> = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            chunks.push(div_small(&mut mag, 1_000_000_000));
+        }
+        let mut s = match chunks.pop() {
+            Some(top) => top.to_string(),
+            None => "0".to_string(),
+        };
+        for c in chunks.iter().rev() {
+            s.push_str(&format!("{:09}", c));
+        }
+        return f.pad_integral(!self.neg, "", &s);
+    }
+}
+
+impl std::ops::Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        return BigInt::from_mag(!self.neg, self.mag.clone());
+    }
+}
+
+impl std::ops::Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.neg == other.neg {
+            return BigInt::from_mag(self.neg, add_mag(&self.mag, &other.mag));
+        }
+        // Opposite signs: subtract the smaller magnitude from the larger.
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::from_mag(other.neg, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::from_mag(self.neg, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+}
+
+impl std::ops::Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        return self + &(-other);
+    }
+}
+
+impl std::ops::Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut r = vec![0u32; self.mag.len() + other.mag.len()];
+        for (i, &a) in self.mag.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.mag.iter().enumerate() {
+                let t = a as u64 * b as u64 + r[i + j] as u64 + carry;
+                r[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            r[i + other.mag.len()] = carry as u32;
+        }
+        return BigInt::from_mag(self.neg != other.neg, r);
+    }
+}
+
+impl std::ops::Not for &BigInt {
+    type Output = BigInt;
+
+    fn not(self) -> BigInt {
+        return &(-self) - &BigInt::from(1);
+    }
+}
+
+impl std::ops::BitAnd for &BigInt {
+    type Output = BigInt;
+
+    fn bitand(self, other: &BigInt) -> BigInt {
+        return self.bitwise(other, |a, b| a & b);
+    }
+}
+
+impl std::ops::BitOr for &BigInt {
+    type Output = BigInt;
+
+    fn bitor(self, other: &BigInt) -> BigInt {
+        return self.bitwise(other, |a, b| a | b);
+    }
+}
+
+impl std::ops::BitXor for &BigInt {
+    type Output = BigInt;
+
+    fn bitxor(self, other: &BigInt) -> BigInt {
+        return self.bitwise(other, |a, b| a ^ b);
+    }
+}
+
+impl std::ops::Shl<usize> for &BigInt {
+    type Output = BigInt;
+
+    fn shl(self, n: usize) -> BigInt {
+        return BigInt::from_mag(self.neg, shl_mag(&self.mag, n));
+    }
+}
+
+// Rounds towards negative infinity like `>>` on i64.
+impl std::ops::Shr<usize> for &BigInt {
+    type Output = BigInt;
+
+    fn shr(self, n: usize) -> BigInt {
+        let q = BigInt::from_mag(self.neg, shr_mag(&self.mag, n));
+        if self.neg && shl_mag(&q.mag, n) != self.mag {
+            return &q - &BigInt::from(1);
+        }
+        return q;
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    if a.len() != b.len() {
+        return a.len().cmp(&b.len());
+    }
+    return a.iter().rev().cmp(b.iter().rev());
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut r = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &l) in long.iter().enumerate() {
+        let t = l as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        r.push(t as u32);
+        carry = t >> 32;
+    }
+    r.push(carry as u32);
+    return r;
+}
+
+// `a - b` for `a >= b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut r = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &l) in a.iter().enumerate() {
+        let t = l as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        r.push(t as u32);
+        borrow = (t < 0) as i64;
+    }
+    return r;
+}
+
+fn mul_small(mag: &mut Vec<u32>, m: u32) {
+    let mut carry = 0u64;
+    for l in mag.iter_mut() {
+        let t = *l as u64 * m as u64 + carry;
+        *l = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        mag.push(carry as u32);
+    }
+}
+
+fn add_small(mag: &mut Vec<u32>, a: u32) {
+    let mut carry = a as u64;
+    for l in mag.iter_mut() {
+        if carry == 0 {
+            return;
+        }
+        let t = *l as u64 + carry;
+        *l = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        mag.push(carry as u32);
+    }
+}
+
+// Divides in place, trimming the result, and returns the remainder.
+fn div_small(mag: &mut Vec<u32>, d: u32) -> u32 {
+    let mut rem = 0u64;
+    for l in mag.iter_mut().rev() {
+        let t = (rem << 32) | *l as u64;
+        *l = (t / d as u64) as u32;
+        rem = t % d as u64;
+    }
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    return rem as u32;
+}
+
+fn shl_mag(a: &[u32], n: usize) -> Vec<u32> {
+    if a.is_empty() {
+        return Vec::new();
+    }
+    let (limbs, bits) = (n / 32, n % 32);
+    let mut r = vec![0u32; limbs];
+    if bits == 0 {
+        r.extend_from_slice(a);
+    } else {
+        let mut carry = 0u32;
+        for &l in a {
+            r.push((l << bits) | carry);
+            carry = l >> (32 - bits);
+        }
+        r.push(carry);
+    }
+    while r.last() == Some(&0) {
+        r.pop();
+    }
+    return r;
+}
+
+fn shr_mag(a: &[u32], n: usize) -> Vec<u32> {
+    let (limbs, bits) = (n / 32, n % 32);
+    if limbs >= a.len() {
+        return Vec::new();
+    }
+    let a = &a[limbs..];
+    let mut r: Vec<u32> = if bits == 0 {
+        a.to_vec()
+    } else {
+        (0..a.len())
+            .map(|i| (a[i] >> bits) | (a.get(i + 1).map_or(0, |&h| h << (32 - bits))))
+            .collect()
+    };
+    while r.last() == Some(&0) {
+        r.pop();
+    }
+    return r;
+}
+
+// Schoolbook long division (Knuth, TAOCP 4.3.1, algorithm D).
+fn div_rem_mag(u: &[u32], v: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    assert!(!v.is_empty(), "BigInt division by zero");
+    if cmp_mag(u, v) == Ordering::Less {
+        return (Vec::new(), u.to_vec());
+    }
+    if v.len() == 1 {
+        let mut q = u.to_vec();
+        let r = div_small(&mut q, v[0]);
+        return (q, if r == 0 { Vec::new() } else { vec![r] });
+    }
+
+    // Normalize so the divisor's top limb has its high bit set, which
+    // keeps each estimated quotient digit at most two too large.
+    let s = v[v.len() - 1].leading_zeros() as usize;
+    let vn = shl_mag(v, s);
+    let mut un = shl_mag(u, s);
+    un.resize(u.len() + 1, 0);
+    let n = vn.len();
+    let m = u.len() - n;
+    let mut q = vec![0u32; m + 1];
+
+    for j in (0..=m).rev() {
+        let num = ((un[j + n] as u64) << 32) | un[j + n - 1] as u64;
+        let mut qhat = num / vn[n - 1] as u64;
+        let mut rhat = num % vn[n - 1] as u64;
+        while qhat >> 32 != 0 || qhat * vn[n - 2] as u64 > ((rhat << 32) | un[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += vn[n - 1] as u64;
+            if rhat >> 32 != 0 {
+                break;
+            }
+        }
+
+        // un[j..=j+n] -= qhat * vn
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let p = qhat * vn[i] as u64 + carry;
+            carry = p >> 32;
+            let t = un[i + j] as i64 - borrow - (p & 0xffff_ffff) as i64;
+            un[i + j] = t as u32;
+            borrow = (t < 0) as i64;
+        }
+        let t = un[j + n] as i64 - borrow - carry as i64;
+        un[j + n] = t as u32;
+
+        if t < 0 {
+            // qhat was one too large: add the divisor back.
+            qhat -= 1;
+            let mut c = 0u64;
+            for i in 0..n {
+                let s = un[i + j] as u64 + vn[i] as u64 + c;
+                un[i + j] = s as u32;
+                c = s >> 32;
+            }
+            un[j + n] = un[j + n].wrapping_add(c as u32);
+        }
+        q[j] = qhat as u32;
+    }
+
+    un.truncate(n);
+    return (q, shr_mag(&un, s));
+}
@@ -0,0 +1,486 @@
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::decimal::Decimal;
+use crate::lexer::{Diagnostic, Span};
+use crate::parser::{BinOp, Expr, ExprKind, LogicOp, UnaryOp};
+use crate::units::Quantity;
+use crate::vm::binop_symbol;
+
+/// A compiled program: instructions for the stack VM, run from the first.
+#[derive(Clone, Debug)]
+pub struct ByteCodes {
+    pub codes : Vec<ByteCode>
+}
+
+impl ByteCodes {
+    fn new() -> Self {
+        Self {
+            codes : Vec::new()
+        }
+    }
+
+    fn add_code(&mut self, code : ByteCode) {
+        self.codes.push(code);
+    }
+}
+
+/// A compiled user-defined function. Parameters occupy the first `arity`
+/// local slots, the remaining slots are the names the body assigns to.
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub locals: Vec<String>,
+    pub code: ByteCodes,
+}
+
+// Functions are compared by identity.
+impl PartialEq for Function {
+    fn eq(&self, other: &Function) -> bool {
+        return std::ptr::eq(self, other);
+    }
+}
+
+/// One VM instruction. Operands live on a value stack; jump targets are
+/// indices into the enclosing `ByteCodes`.
+#[derive(Clone, Debug)]
+#[allow(non_camel_case_types)]
+pub enum ByteCode {
+    PUSHI(i64), // i32 is a int value
+    PUSHBIG(Rc<BigInt>), // an integer literal beyond i64
+    PUSHDEC(Rc<Decimal>),
+    PUSHF(f64), // f32 is a float value
+    PUSHIM(f64), // an imaginary literal
+    PUSHQ(Rc<Quantity>), // a unit, as one of it
+    PUSHB(bool),
+    BINOP(i32), // i32 is opc 0: ADD, 1: SUB, 2: MUL, 3: DIV, 4: POW,
+                //            5: EQ, 6: NE, 7: LT, 8: LE, 9: GT, 10: GE,
+                //            11: MOD, 12: FLOORDIV, 13: AND, 14: OR, 15: XOR, 16: SHL, 17: SHR, 18: IN
+    UNARYOP(i32), // i32 is opc 0: ADD 1: SUB 2: NOT 3: INVERT
+    LOAD(String), // push the global with this name
+    STORE(String), // bind the global to the top of stack, leaving it there
+    JUMP(usize), // continue at this instruction index
+    JUMP_IF_FALSE(usize), // pop a Bool, jump if it is false
+    JUMP_IF_TRUE(usize), // pop a Bool, jump if it is true
+    PUSHU, // push Unit, the value of loops and statements
+    POP,
+    FOR_ITER(usize), // with [counter, end] on top: push the counter and bump it,
+                     // or jump here once it reaches end
+    LOAD_LOCAL(usize), // push a local slot of the current call frame
+    STORE_LOCAL(usize), // set a local slot to the top of stack, leaving it there
+    PUSHFN(Rc<Function>),
+    CALL(usize), // usize is the argument count; the callee sits below the arguments
+    RET // return the top of stack to the caller
+}
+
+impl ByteCode {
+    // How many values the instruction leaves on the stack compared to
+    // before it, when execution falls through to the next instruction.
+    fn stack_effect(&self) -> isize {
+        match self {
+            ByteCode::PUSHI(_) | ByteCode::PUSHBIG(_) | ByteCode::PUSHDEC(_) | ByteCode::PUSHF(_) | ByteCode::PUSHIM(_) | ByteCode::PUSHQ(_) | ByteCode::PUSHB(_) | ByteCode::PUSHU => 1,
+            ByteCode::LOAD(_) | ByteCode::FOR_ITER(_) => 1,
+            ByteCode::LOAD_LOCAL(_) | ByteCode::PUSHFN(_) => 1,
+            ByteCode::BINOP(_) | ByteCode::POP | ByteCode::RET => -1,
+            ByteCode::JUMP_IF_FALSE(_) | ByteCode::JUMP_IF_TRUE(_) => -1,
+            ByteCode::UNARYOP(_) | ByteCode::STORE(_) | ByteCode::JUMP(_) => 0,
+            ByteCode::STORE_LOCAL(_) => 0,
+            ByteCode::CALL(argc) => -(*argc as isize),
+        }
+    }
+}
+
+impl ByteCodes {
+    /// A listing of the instructions, one per line with its index. The code
+    /// of each function defined here follows under the function's name.
+    pub fn dis(&self) -> String {
+        let mut out = String::new();
+        let mut funcs = Vec::new();
+        for (i, code) in self.codes.iter().enumerate() {
+            out.push_str(&format!("{:4} {}\n", i, code));
+            if let ByteCode::PUSHFN(f) = code {
+                funcs.push(f.clone());
+            }
+        }
+        for f in funcs {
+            out.push_str(&format!("\nfn {}({}):\n", f.name, f.locals[..f.arity].join(", ")));
+            out.push_str(&f.code.dis());
+        }
+        return out;
+    }
+}
+
+// The mnemonic and its operand, e.g. `PUSHI 1` or `BINOP +`.
+impl std::fmt::Display for ByteCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ByteCode::PUSHI(i) => write!(f, "PUSHI {}", i),
+            ByteCode::PUSHBIG(i) => write!(f, "PUSHBIG {}", i),
+            ByteCode::PUSHDEC(d) => write!(f, "PUSHDEC {}", d),
+            ByteCode::PUSHF(x) => write!(f, "PUSHF {}", x),
+            ByteCode::PUSHIM(x) => write!(f, "PUSHIM {}", x),
+            ByteCode::PUSHQ(q) => write!(f, "PUSHQ {}", q),
+            ByteCode::PUSHB(b) => write!(f, "PUSHB {}", b),
+            ByteCode::BINOP(opc) => write!(f, "BINOP {}", binop_symbol(*opc)),
+            ByteCode::UNARYOP(opc) => write!(f, "UNARYOP {}", ["+", "-", "!", "~"].get(*opc as usize).unwrap_or(&"?")),
+            ByteCode::LOAD(name) => write!(f, "LOAD {}", name),
+            ByteCode::STORE(name) => write!(f, "STORE {}", name),
+            ByteCode::JUMP(t) => write!(f, "JUMP {}", t),
+            ByteCode::JUMP_IF_FALSE(t) => write!(f, "JUMP_IF_FALSE {}", t),
+            ByteCode::JUMP_IF_TRUE(t) => write!(f, "JUMP_IF_TRUE {}", t),
+            ByteCode::PUSHU => write!(f, "PUSHU"),
+            ByteCode::POP => write!(f, "POP"),
+            ByteCode::FOR_ITER(t) => write!(f, "FOR_ITER {}", t),
+            ByteCode::LOAD_LOCAL(slot) => write!(f, "LOAD_LOCAL {}", slot),
+            ByteCode::STORE_LOCAL(slot) => write!(f, "STORE_LOCAL {}", slot),
+            ByteCode::PUSHFN(func) => write!(f, "PUSHFN {}", func.name),
+            ByteCode::CALL(argc) => write!(f, "CALL {}", argc),
+            ByteCode::RET => write!(f, "RET"),
+        }
+    }
+}
+
+// Book-keeping for the innermost loops while compiling their bodies.
+pub struct Loop {
+    depth: usize, // operand stack depth a `break`/`continue` unwinds to
+    head: usize, // where `continue` jumps
+    breaks: Vec<usize>, // `break` jumps, patched to the loop exit
+}
+
+pub struct Dis {
+    b : ByteCodes,
+    depth : usize, // operand stack depth at this point of the code
+    loops : Vec<Loop>,
+    locals : Vec<String>, // slot names while compiling a function body
+    error : Option<Diagnostic>, // the first node found with no translation
+}
+
+impl Dis {
+    pub fn new() -> Dis {
+        Dis {
+            b : ByteCodes::new(),
+            depth : 0,
+            loops : Vec::new(),
+            locals : Vec::new(),
+            error : None,
+        }
+    }
+
+    fn local(&self, name: &str) -> Option<usize> {
+        return self.locals.iter().position(|l| l == name);
+    }
+
+    fn function(name: &str, params: &[String], body: &Expr) -> Result<Function, Diagnostic> {
+        let mut locals = params.to_vec();
+        body.assigned_names(&mut locals);
+
+        let mut dis = Dis::new();
+        dis.locals = locals.clone();
+        dis.expr(body);
+        dis.emit(ByteCode::RET);
+        if let Some(d) = dis.error {
+            return Err(d);
+        }
+        return Ok(Function {
+            name: name.to_string(),
+            arity: params.len(),
+            locals,
+            code: dis.b,
+        });
+    }
+
+    /// Fails only on trees the parser would have rejected, such as a
+    /// `break` outside a loop built by hand.
+    pub fn dis(&mut self, asts: &Expr) -> Result<ByteCodes, Diagnostic> {
+        self.expr(asts);
+        if let Some(d) = self.error.take() {
+            return Err(d);
+        }
+        return Ok(self.b.clone());
+    }
+
+    // Records an error; compiling carries on, but its output is discarded.
+    fn fail(&mut self, message: String, span: Span) {
+        if self.error.is_none() {
+            self.error = Some(Diagnostic::new(message, span));
+        }
+    }
+
+    fn emit(&mut self, code: ByteCode) {
+        self.depth = self.depth.wrapping_add_signed(code.stack_effect());
+        self.b.add_code(code);
+    }
+
+    // Emits a jump with a placeholder target and returns its index for `patch`.
+    fn jump(&mut self, code: fn(usize) -> ByteCode) -> usize {
+        self.emit(code(usize::MAX));
+        return self.b.codes.len() - 1;
+    }
+
+    // Points the jump at `at` to the next instruction to be emitted.
+    fn patch(&mut self, at: usize) {
+        let target = self.b.codes.len();
+        match &mut self.b.codes[at] {
+            ByteCode::JUMP(t) | ByteCode::JUMP_IF_FALSE(t) | ByteCode::JUMP_IF_TRUE(t) | ByteCode::FOR_ITER(t) => {
+                *t = target;
+            },
+            _ => unreachable!("patching a non-jump instruction"),
+        }
+    }
+
+    // Drops whatever the enclosing expressions of a `break`/`continue` left
+    // on the stack, so the loop continues from a known depth.
+    fn unwind(&mut self) {
+        let target = self.loops.last().unwrap().depth;
+        while self.depth > target {
+            self.emit(ByteCode::POP);
+        }
+    }
+
+    // The rest of `lhs op rhs` once lhs has been compiled.
+    fn binary(&mut self, op: BinOp, rhs: &Expr) {
+        self.expr(rhs);
+        let opc = match op {
+            BinOp::Add => 0,
+            BinOp::Sub => 1,
+            BinOp::Mul => 2,
+            BinOp::Div => 3,
+            BinOp::Pow => 4,
+
+            BinOp::Eq => 5,
+            BinOp::Ne => 6,
+            BinOp::Lt => 7,
+            BinOp::Le => 8,
+            BinOp::Gt => 9,
+            BinOp::Ge => 10,
+
+            BinOp::Mod => 11,
+            BinOp::FloorDiv => 12,
+            BinOp::BitAnd => 13,
+            BinOp::BitOr => 14,
+            BinOp::BitXor => 15,
+            BinOp::Shl => 16,
+            BinOp::Shr => 17,
+
+            BinOp::In => 18,
+        };
+        self.emit(ByteCode::BINOP(opc));
+    }
+
+    // The rest of `lhs && rhs` or `lhs || rhs` once lhs has been compiled;
+    // `depth` is the stack depth from before lhs.
+    //
+    // a && b:   a; JUMP_IF_FALSE no; b; JUMP_IF_FALSE no; PUSHB true; JUMP end
+    //           no: PUSHB false
+    //           end:
+    // `||` is the mirror image with JUMP_IF_TRUE. Both operands go through
+    // a conditional jump so each must be a Bool.
+    fn logical(&mut self, op: LogicOp, rhs: &Expr, depth: usize) {
+        let (test, short): (fn(usize) -> ByteCode, bool) = match op {
+            LogicOp::And => (ByteCode::JUMP_IF_FALSE, false),
+            LogicOp::Or => (ByteCode::JUMP_IF_TRUE, true),
+        };
+        let first = self.jump(test);
+        self.expr(rhs);
+        let second = self.jump(test);
+        self.emit(ByteCode::PUSHB(!short));
+        let end = self.jump(ByteCode::JUMP);
+        self.patch(first);
+        self.patch(second);
+        self.depth = depth;
+        self.emit(ByteCode::PUSHB(short));
+        self.patch(end);
+    }
+
+    fn expr(&mut self, asts: &Expr) {
+        match &asts.kind {
+            ExprKind::Int(i) => {
+                self.emit(ByteCode::PUSHI(*i));
+            },
+            ExprKind::BigInt(i) => {
+                self.emit(ByteCode::PUSHBIG(Rc::new(i.clone())));
+            },
+            ExprKind::Decimal(d) => {
+                self.emit(ByteCode::PUSHDEC(Rc::new(d.clone())));
+            },
+            ExprKind::Float(f) => {
+                self.emit(ByteCode::PUSHF(*f));
+            },
+            ExprKind::Imag(f) => {
+                self.emit(ByteCode::PUSHIM(*f));
+            },
+            ExprKind::Quantity(u) => {
+                self.emit(ByteCode::PUSHQ(Rc::new(Quantity { value: 1.0, unit: u.clone() })));
+            },
+            ExprKind::Bool(b) => {
+                self.emit(ByteCode::PUSHB(*b));
+            },
+            ExprKind::Var(name) => match self.local(name) {
+                Some(slot) => self.emit(ByteCode::LOAD_LOCAL(slot)),
+                None => self.emit(ByteCode::LOAD(name.clone())),
+            },
+            ExprKind::Assign { name, value } => {
+                self.expr(value);
+                self.store(name);
+            },
+            ExprKind::Binary { .. } | ExprKind::Logical { .. } => {
+                // Each operator along the chain only adds to the code of its
+                // left operand, so the chain is compiled from the inside out.
+                let depth = self.depth;
+                let (leaf, spine) = asts.left_spine();
+                self.expr(leaf);
+                for e in spine.iter().rev() {
+                    match &e.kind {
+                        ExprKind::Binary { op, rhs, .. } => self.binary(*op, rhs),
+                        ExprKind::Logical { op, rhs, .. } => self.logical(*op, rhs, depth),
+                        _ => unreachable!(),
+                    }
+                }
+            },
+            ExprKind::Unary { op, operand } => {
+                self.expr(operand);
+                let opc = match op {
+                    UnaryOp::Plus => 0,
+                    UnaryOp::Neg => 1,
+                    UnaryOp::Not => 2,
+                    UnaryOp::BitNot => 3,
+                };
+                self.emit(ByteCode::UNARYOP(opc));
+            },
+            ExprKind::If { cond, then, otherwise } => {
+                // cond; JUMP_IF_FALSE else; then; JUMP end; else: otherwise; end:
+                //
+                // Without an else branch the `if` is Unit either way:
+                // cond; JUMP_IF_FALSE end; then; POP; end: PUSHU
+                self.expr(cond);
+                let to_else = self.jump(ByteCode::JUMP_IF_FALSE);
+                let depth = self.depth;
+                self.expr(then);
+                match otherwise {
+                    Some(otherwise) => {
+                        let to_end = self.jump(ByteCode::JUMP);
+                        self.patch(to_else);
+                        self.depth = depth;
+                        self.expr(otherwise);
+                        self.patch(to_end);
+                    },
+                    None => {
+                        self.emit(ByteCode::POP);
+                        self.patch(to_else);
+                        self.emit(ByteCode::PUSHU);
+                    }
+                }
+            },
+            ExprKind::Block { body, value } | ExprKind::Program { body, value } => {
+                for (i, stmt) in body.iter().enumerate() {
+                    self.expr(stmt);
+                    if i + 1 < body.len() || !value {
+                        self.emit(ByteCode::POP);
+                    }
+                }
+                if !value {
+                    self.emit(ByteCode::PUSHU);
+                }
+            },
+            ExprKind::While { cond, body } => {
+                // head: cond; JUMP_IF_FALSE exit; body; POP; JUMP head; exit: PUSHU
+                let head = self.b.codes.len();
+                self.expr(cond);
+                let exit = self.jump(ByteCode::JUMP_IF_FALSE);
+                self.loop_body(body, head);
+                let breaks = self.loops.pop().unwrap().breaks;
+                self.emit(ByteCode::JUMP(head));
+                self.patch(exit);
+                for b in breaks {
+                    self.patch(b);
+                }
+                self.emit(ByteCode::PUSHU);
+            },
+            ExprKind::For { var, start, end, inclusive, body } => {
+                // start; end; head: FOR_ITER exit; STORE var; POP; body; POP; JUMP head
+                // exit: POP; POP; PUSHU
+                self.expr(start);
+                self.expr(end);
+                if *inclusive {
+                    self.emit(ByteCode::PUSHI(1));
+                    self.emit(ByteCode::BINOP(0));
+                }
+                let head = self.b.codes.len();
+                let exit = self.jump(ByteCode::FOR_ITER);
+                self.store(var);
+                self.emit(ByteCode::POP);
+                self.loop_body(body, head);
+                let breaks = self.loops.pop().unwrap().breaks;
+                self.emit(ByteCode::JUMP(head));
+                self.patch(exit);
+                for b in breaks {
+                    self.patch(b);
+                }
+                self.emit(ByteCode::POP);
+                self.emit(ByteCode::POP);
+                self.emit(ByteCode::PUSHU);
+            },
+            ExprKind::Break | ExprKind::Continue if self.loops.is_empty() => {
+                let word = if matches!(asts.kind, ExprKind::Break) { "break" } else { "continue" };
+                self.fail(format!("'{}' outside of a loop", word), asts.span);
+            },
+            ExprKind::Break => {
+                let depth = self.depth;
+                self.unwind();
+                let at = self.jump(ByteCode::JUMP);
+                self.loops.last_mut().unwrap().breaks.push(at);
+                // Nothing after this runs, but the enclosing expression
+                // still expects a value to have been pushed.
+                self.depth = depth + 1;
+            },
+            ExprKind::Continue => {
+                let depth = self.depth;
+                self.unwind();
+                let head = self.loops.last().unwrap().head;
+                self.emit(ByteCode::JUMP(head));
+                self.depth = depth + 1;
+            },
+            ExprKind::FnDef { name, params, body } => {
+                let f = match Dis::function(name, params, body) {
+                    Ok(f) => f,
+                    Err(d) => {
+                        self.error.get_or_insert(d);
+                        return;
+                    }
+                };
+                self.emit(ByteCode::PUSHFN(Rc::new(f)));
+                self.emit(ByteCode::STORE(name.clone()));
+                self.emit(ByteCode::POP);
+                self.emit(ByteCode::PUSHU);
+            },
+            ExprKind::Call { callee, args } => {
+                // callee; args...; CALL argc
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(ByteCode::CALL(args.len()));
+            }
+        }
+    }
+
+    fn store(&mut self, name: &str) {
+        match self.local(name) {
+            Some(slot) => self.emit(ByteCode::STORE_LOCAL(slot)),
+            None => self.emit(ByteCode::STORE(name.to_string())),
+        }
+    }
+
+    // Compiles a loop body that discards its value and leaves the `Loop`
+    // on `self.loops` for the caller to collect the breaks from.
+    fn loop_body(&mut self, body: &Expr, head: usize) {
+        self.loops.push(Loop {
+            depth: self.depth,
+            head,
+            breaks: Vec::new(),
+        });
+        self.expr(body);
+        self.emit(ByteCode::POP);
+    }
+}
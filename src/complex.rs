@@ -0,0 +1,206 @@
+use std::f64::consts::{FRAC_PI_2, LN_10, LN_2};
+
+/// A complex number with Float parts.
+///
+/// The math library returns principal values, so `sqrt(-4)` is `2i` and
+/// `ln(-1)` is `pi * i`. A result whose imaginary part is exactly zero
+/// becomes a Float again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    /// The imaginary unit, which scripts call `i`.
+    pub const I: Complex = Complex { re: 0.0, im: 1.0 };
+
+    pub fn new(re: f64, im: f64) -> Complex {
+        return Complex { re, im };
+    }
+
+    pub fn from_polar(r: f64, theta: f64) -> Complex {
+        return Complex { re: r * theta.cos(), im: r * theta.sin() };
+    }
+
+    /// The modulus `|z|`.
+    pub fn abs(self) -> f64 {
+        return self.re.hypot(self.im);
+    }
+
+    /// The angle to the positive real axis, in `(-pi, pi]`.
+    pub fn arg(self) -> f64 {
+        return self.im.atan2(self.re);
+    }
+
+    pub fn conj(self) -> Complex {
+        return Complex { re: self.re, im: -self.im };
+    }
+
+    pub fn is_zero(self) -> bool {
+        return self.re == 0.0 && self.im == 0.0;
+    }
+
+    pub fn exp(self) -> Complex {
+        return Complex::from_polar(self.re.exp(), self.im);
+    }
+
+    pub fn ln(self) -> Complex {
+        return Complex { re: self.abs().ln(), im: self.arg() };
+    }
+
+    pub fn log2(self) -> Complex {
+        return self.ln() / Complex::from(LN_2);
+    }
+
+    pub fn log10(self) -> Complex {
+        return self.ln() / Complex::from(LN_10);
+    }
+
+    pub fn sqrt(self) -> Complex {
+        // Computed from the modulus rather than the polar form so that
+        // square roots of negative reals come out exact, e.g. sqrt(-4) = 2i.
+        let r = self.abs();
+        let re = ((r + self.re) / 2.0).sqrt();
+        let im = ((r - self.re) / 2.0).sqrt();
+        return Complex { re, im: if self.im.is_sign_negative() { -im } else { im } };
+    }
+
+    pub fn cbrt(self) -> Complex {
+        return Complex::from_polar(self.abs().cbrt(), self.arg() / 3.0);
+    }
+
+    /// `self ^ w`. Integer powers multiply out so `(1+i)^2` is exactly `2i`.
+    pub fn pow(self, w: Complex) -> Complex {
+        if w.im == 0.0 && w.re.fract() == 0.0 && w.re.abs() <= i32::MAX as f64 {
+            return self.powi(w.re as i32);
+        }
+        if self.is_zero() {
+            return if w.re > 0.0 { Complex::from(0.0) } else { Complex::from(f64::NAN) };
+        }
+        return (w * self.ln()).exp();
+    }
+
+    fn powi(self, n: i32) -> Complex {
+        let mut r = Complex::from(1.0);
+        let mut base = self;
+        let mut e = n.unsigned_abs();
+        while e > 0 {
+            if e & 1 == 1 {
+                r = r * base;
+            }
+            base = base * base;
+            e >>= 1;
+        }
+        return if n < 0 { Complex::from(1.0) / r } else { r };
+    }
+
+    pub fn sin(self) -> Complex {
+        return Complex { re: self.re.sin() * self.im.cosh(), im: self.re.cos() * self.im.sinh() };
+    }
+
+    pub fn cos(self) -> Complex {
+        return Complex { re: self.re.cos() * self.im.cosh(), im: -self.re.sin() * self.im.sinh() };
+    }
+
+    pub fn tan(self) -> Complex {
+        return self.sin() / self.cos();
+    }
+
+    pub fn sinh(self) -> Complex {
+        return Complex { re: self.re.sinh() * self.im.cos(), im: self.re.cosh() * self.im.sin() };
+    }
+
+    pub fn cosh(self) -> Complex {
+        return Complex { re: self.re.cosh() * self.im.cos(), im: self.re.sinh() * self.im.sin() };
+    }
+
+    pub fn tanh(self) -> Complex {
+        return self.sinh() / self.cosh();
+    }
+
+    // asin(z) = -i ln(iz + sqrt(1 - z) sqrt(1 + z)); the factored root and
+    // the signed zero in `1 - z` put the branch cuts where C and Python do.
+    pub fn asin(self) -> Complex {
+        let below = Complex { re: 1.0 - self.re, im: -self.im };
+        let above = Complex { re: 1.0 + self.re, im: self.im };
+        return -Complex::I * (Complex::I * self + below.sqrt() * above.sqrt()).ln();
+    }
+
+    pub fn acos(self) -> Complex {
+        return Complex::from(FRAC_PI_2) - self.asin();
+    }
+
+    // atan(z) = i/2 (ln(1 - iz) - ln(1 + iz))
+    pub fn atan(self) -> Complex {
+        let one = Complex::from(1.0);
+        let iz = Complex::I * self;
+        return Complex::new(0.0, 0.5) * ((one - iz).ln() - (one + iz).ln());
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Complex {
+        return Complex { re, im: 0.0 };
+    }
+}
+
+impl std::ops::Neg for Complex {
+    type Output = Complex;
+
+    fn neg(self) -> Complex {
+        return Complex { re: -self.re, im: -self.im };
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        return Complex { re: self.re + other.re, im: self.im + other.im };
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        return Complex { re: self.re - other.re, im: self.im - other.im };
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        return Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        };
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let d = other.re * other.re + other.im * other.im;
+        return Complex {
+            re: (self.re * other.re + self.im * other.im) / d,
+            im: (self.im * other.re - self.re * other.im) / d,
+        };
+    }
+}
+
+// `3+4i`, `-2.5i`; the parts print like Floats.
+impl std::fmt::Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.re == 0.0 {
+            return write!(f, "{}i", self.im);
+        }
+        if self.im.is_sign_negative() {
+            return write!(f, "{}-{}i", self.re, -self.im);
+        }
+        write!(f, "{}+{}i", self.re, self.im)
+    }
+}
@@ -0,0 +1,299 @@
+use std::cmp::Ordering;
+
+use crate::bigint::{BigInt, MAX_BITS};
+use crate::rational::Rational;
+
+// Largest number of digits after (or zeros before) the point. Aligning two
+// operands multiplies by a power of ten this large, so it keeps that power
+// within the integer size limit.
+pub const MAX_SCALE: i64 = 100_000;
+
+/// Most significant digits a [`Context`] keeps. A result that precise can
+/// already need the largest scale, so more digits would only overflow, and
+/// take a long time doing it.
+pub const MAX_PRECISION: u32 = MAX_SCALE as u32;
+
+/// How a decimal result with too many digits is cut back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    HalfEven, // to nearest, ties to the even neighbour (banker's rounding)
+    HalfUp,   // to nearest, ties away from zero
+    Down,     // towards zero
+    Up,       // away from zero
+    Floor,    // towards negative infinity
+    Ceiling,  // towards positive infinity
+}
+
+const ROUNDINGS: [(&str, Rounding); 6] = [
+    ("half-even", Rounding::HalfEven),
+    ("half-up", Rounding::HalfUp),
+    ("down", Rounding::Down),
+    ("up", Rounding::Up),
+    ("floor", Rounding::Floor),
+    ("ceiling", Rounding::Ceiling),
+];
+
+impl Rounding {
+    /// Looks a mode up by the name `:set rounding` uses, e.g. `half-even`.
+    pub fn from_name(name: &str) -> Option<Rounding> {
+        return ROUNDINGS.iter().find(|(n, _)| *n == name).map(|(_, r)| *r);
+    }
+
+    pub fn name(&self) -> &'static str {
+        return ROUNDINGS.iter().find(|(_, r)| r == self).map_or("?", |(n, _)| n);
+    }
+}
+
+/// Precision and rounding applied to every decimal result.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Context {
+    pub precision: u32, // significant digits, 1 to MAX_PRECISION
+    pub rounding: Rounding,
+}
+
+impl Default for Context {
+    fn default() -> Context {
+        return Context { precision: 28, rounding: Rounding::HalfEven };
+    }
+}
+
+/// A base-10 number `coef * 10^-scale`, so `0.1` is stored exactly.
+///
+/// Trailing zeros are significant the way they are on paper: `1.10 * 2` is
+/// `2.20`. Results are rounded to the session's [`Context`]. Equality
+/// goes by value all the same, so `1.0 == 1.00`.
+#[derive(Clone, Debug)]
+pub struct Decimal {
+    coef: BigInt,
+    scale: i64, // digits after the point; negative means trailing zeros
+}
+
+fn ten_pow(n: u64) -> BigInt {
+    return BigInt::from(10).pow(n.min(u32::MAX as u64) as u32);
+}
+
+// Number of decimal digits in |i|; 1 for zero.
+fn digits(i: &BigInt) -> u64 {
+    return i.abs().to_string().len() as u64;
+}
+
+impl Decimal {
+    pub fn new(coef: BigInt, scale: i64) -> Decimal {
+        return Decimal { coef, scale };
+    }
+
+    /// Parses a literal such as `12`, `-0.10` or `1.5e-3`; `_` is not allowed.
+    pub fn parse(s: &str) -> Option<Decimal> {
+        if let Some(rest) = s.strip_prefix('-') {
+            return Decimal::parse(rest).map(|d| -&d);
+        }
+        let (mantissa, exp) = match s.find(['e', 'E']) {
+            Some(i) => (&s[..i], s[i + 1..].parse::<i64>().ok()?),
+            None => (s, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let coef = BigInt::parse(&format!("{}{}", int, frac), 10)?;
+        let d = Decimal { coef, scale: (frac.len() as i64).checked_sub(exp)? };
+        return if d.in_range() { Some(d) } else { None };
+    }
+
+    pub fn scale(&self) -> i64 {
+        return self.scale;
+    }
+
+    /// Whether the scale is within `MAX_SCALE`; results outside overflow.
+    pub fn in_range(&self) -> bool {
+        return self.scale.abs() <= MAX_SCALE;
+    }
+
+    pub fn is_zero(&self) -> bool {
+        return self.coef.is_zero();
+    }
+
+    pub fn is_negative(&self) -> bool {
+        return self.coef.is_negative();
+    }
+
+    pub fn to_rational(&self) -> Rational {
+        if self.scale <= 0 {
+            return Rational::from(&self.coef * &ten_pow(self.scale.unsigned_abs()));
+        }
+        return Rational::new(self.coef.clone(), ten_pow(self.scale as u64)).unwrap();
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Going through the decimal string lets the standard library do the
+        // correctly rounded conversion.
+        return format!("{}e{}", self.coef, -self.scale).parse().unwrap_or(f64::NAN);
+    }
+
+    pub fn abs(&self) -> Decimal {
+        return Decimal { coef: self.coef.abs(), scale: self.scale };
+    }
+
+    /// Same value with exactly `scale` digits after the point, rounding
+    /// away any digits that no longer fit.
+    pub fn rescale(&self, scale: i64, mode: Rounding) -> Decimal {
+        if scale >= self.scale {
+            let coef = &self.coef * &ten_pow((scale - self.scale) as u64);
+            return Decimal { coef, scale };
+        }
+        let unit = ten_pow((self.scale - scale) as u64);
+        let (q, r) = self.coef.div_rem(&unit);
+        if r.is_zero() {
+            return Decimal { coef: q, scale };
+        }
+        // How the dropped part compares with half a unit.
+        let half = (&r.abs() * &BigInt::from(2)).cmp(&unit);
+        let odd = !(&q & &BigInt::from(1)).is_zero();
+        let away = match mode {
+            Rounding::HalfEven => half == Ordering::Greater || (half == Ordering::Equal && odd),
+            Rounding::HalfUp => half != Ordering::Less,
+            Rounding::Down => false,
+            Rounding::Up => true,
+            Rounding::Floor => self.coef.is_negative(),
+            Rounding::Ceiling => !self.coef.is_negative(),
+        };
+        let coef = match (away, self.coef.is_negative()) {
+            (false, _) => q,
+            (true, false) => &q + &BigInt::from(1),
+            (true, true) => &q - &BigInt::from(1),
+        };
+        return Decimal { coef, scale };
+    }
+
+    /// Rounds to the context's number of significant digits.
+    pub fn round(&self, ctx: &Context) -> Decimal {
+        let d = digits(&self.coef);
+        if d <= ctx.precision as u64 {
+            return self.clone();
+        }
+        let r = self.rescale(self.scale - (d - ctx.precision as u64) as i64, ctx.rounding);
+        // 9.99 rounding up to 10.0 gains a digit, which is now a zero
+        if digits(&r.coef) > ctx.precision as u64 {
+            return r.rescale(r.scale - 1, ctx.rounding);
+        }
+        return r;
+    }
+
+    /// The integer nearest in the given direction.
+    pub fn to_integer(&self, mode: Rounding) -> BigInt {
+        return self.rescale(0, mode).coef;
+    }
+
+    // Both operands with the larger of the two scales.
+    fn align(&self, other: &Decimal) -> (BigInt, BigInt, i64) {
+        let scale = self.scale.max(other.scale);
+        let a = self.rescale(scale, Rounding::Down).coef;
+        let b = other.rescale(scale, Rounding::Down).coef;
+        return (a, b, scale);
+    }
+
+    pub fn add(&self, other: &Decimal, ctx: &Context) -> Decimal {
+        let (a, b, scale) = self.align(other);
+        return Decimal { coef: &a + &b, scale }.round(ctx);
+    }
+
+    pub fn sub(&self, other: &Decimal, ctx: &Context) -> Decimal {
+        let (a, b, scale) = self.align(other);
+        return Decimal { coef: &a - &b, scale }.round(ctx);
+    }
+
+    pub fn mul(&self, other: &Decimal, ctx: &Context) -> Decimal {
+        return Decimal { coef: &self.coef * &other.coef, scale: self.scale + other.scale }.round(ctx);
+    }
+
+    /// None when dividing by zero.
+    pub fn div(&self, other: &Decimal, ctx: &Context) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        // Scale the dividend so the quotient has a digit to spare beyond
+        // the precision, then fold any remainder into a last sticky digit
+        // so that rounding sees the quotient was not exact.
+        let want = ctx.precision as u64 + 2;
+        let shift = (want + digits(&other.coef)).saturating_sub(digits(&self.coef));
+        let (q, r) = (&self.coef * &ten_pow(shift)).div_rem(&other.coef);
+        let ideal = self.scale - other.scale;
+        let scale = ideal + shift as i64;
+        if !r.is_zero() {
+            let mut coef = &q * &BigInt::from(10);
+            coef = if self.is_negative() != other.is_negative() { &coef - &BigInt::from(1) } else { &coef + &BigInt::from(1) };
+            return Some(Decimal { coef, scale: scale + 1 }.round(ctx));
+        }
+        // Exact: drop the padding zeros again, but only down to `ideal`.
+        let mut d = Decimal { coef: q, scale }.round(ctx);
+        while d.scale > ideal && !d.coef.is_zero() && d.coef.div_rem(&BigInt::from(10)).1.is_zero() {
+            d = Decimal { coef: d.coef.div_rem(&BigInt::from(10)).0, scale: d.scale - 1 };
+        }
+        return Some(d);
+    }
+
+    /// Integer powers; None for a negative power of zero, or a result
+    /// beyond the size limit before rounding.
+    pub fn pow(&self, e: i64, ctx: &Context) -> Option<Decimal> {
+        let n = e.unsigned_abs();
+        if self.coef.bits().saturating_sub(1).saturating_mul(n) > MAX_BITS || n > u32::MAX as u64 {
+            return None;
+        }
+        let p = Decimal { coef: self.coef.pow(n as u32), scale: self.scale.checked_mul(n as i64)? };
+        if e < 0 {
+            // The power is exact, so the reciprocal is only rounded once.
+            let one = Decimal { coef: BigInt::from(1), scale: 0 };
+            return one.div(&p, ctx);
+        }
+        return Some(p.round(ctx));
+    }
+}
+
+impl From<BigInt> for Decimal {
+    fn from(i: BigInt) -> Decimal {
+        return Decimal { coef: i, scale: 0 };
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        let (a, b, _) = self.align(other);
+        return a.cmp(&b);
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl std::ops::Neg for &Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        return Decimal { coef: -&self.coef, scale: self.scale };
+    }
+}
+
+// Plain positional notation, never an exponent: `0.0012`, `1200`.
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let digits = self.coef.abs().to_string();
+        let s = if self.scale <= 0 {
+            let zeros = if self.coef.is_zero() { 0 } else { self.scale.unsigned_abs() as usize };
+            format!("{}{}", digits, "0".repeat(zeros))
+        } else {
+            let scale = self.scale as usize;
+            let padded = format!("{}{}", "0".repeat((scale + 1).saturating_sub(digits.len())), digits);
+            let (int, frac) = padded.split_at(padded.len() - scale);
+            format!("{}.{}", int, frac)
+        };
+        return f.pad_integral(!self.coef.is_negative(), "", &s);
+    }
+}
@@ -0,0 +1,468 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use mds::TokenKind;
+
+// Entries kept in memory and in the history file.
+const MAX_HISTORY: usize = 1000;
+
+/// What a call to [`Editor::read_line`] produced.
+pub enum Input {
+    Line(String),
+    Interrupted, // Ctrl-C: the line, and anything it continued, is dropped
+    Eof,
+}
+
+// A key press, decoded from the bytes the terminal sends.
+#[derive(Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Ctrl(char), // Ctrl-A is Ctrl('a')
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    Escape,
+    Closed, // standard input reached its end
+}
+
+// Puts the terminal into non-canonical, no-echo mode for as long as it
+// lives, using `stty` so that no system bindings are needed.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "min", "1"])?;
+        return Some(RawMode { saved: saved.trim().to_string() });
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+// Runs `stty` on the terminal; None if it is missing or fails.
+fn stty(args: &[&str]) -> Option<String> {
+    let out = Command::new("stty").args(args).stdin(Stdio::inherit()).stderr(Stdio::null()).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    return String::from_utf8(out.stdout).ok();
+}
+
+/// Reads lines with cursor movement, history recall and reverse search
+/// when talking to a terminal, and plain lines otherwise.
+///
+/// Keys follow readline: arrows, Home/End, Ctrl-A/E/B/F to move, Alt-B/F
+/// by word, Ctrl-K/U/W to delete, Up/Down or Ctrl-P/N for history, Ctrl-R
+/// to search it, Ctrl-L to clear the screen. Lines are assumed to fit the
+/// width of the terminal.
+pub struct Editor {
+    history: Vec<String>,
+    file: Option<PathBuf>, // where history is saved; None when not interactive
+    tty: bool,
+}
+
+impl Editor {
+    /// An editor whose history is loaded from and appended to `file`.
+    pub fn new(file: Option<PathBuf>) -> Editor {
+        let tty = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+        let mut editor = Editor { history: Vec::new(), file: if tty { file } else { None }, tty };
+        if let Some(path) = &editor.file
+            && let Ok(text) = std::fs::read_to_string(path)
+        {
+            editor.history = text.lines().map(str::to_string).collect();
+            if trim_history(&mut editor.history) {
+                let _ = std::fs::write(path, editor.history.join("\n") + "\n");
+            }
+        }
+        return editor;
+    }
+
+    /// Adds a line to the history and the history file. Blank lines and
+    /// repeats of the previous line are skipped.
+    pub fn add_history(&mut self, line: &str) {
+        if !self.tty || !push_history(&mut self.history, line) {
+            return;
+        }
+        // Appended right away, so lines survive a session that is killed.
+        if let Some(path) = &self.file
+            && let Ok(mut f) = OpenOptions::new().create(true).append(true).open(path)
+        {
+            let _ = writeln!(f, "{}", line);
+        }
+    }
+
+    /// Reads one line, without its line break. The prompt is only shown
+    /// on a terminal.
+    pub fn read_line(&mut self, prompt: &str) -> std::io::Result<Input> {
+        if self.tty
+            && let Some(_raw) = RawMode::enable()
+        {
+            return self.edit(prompt);
+        }
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(Input::Eof);
+        }
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        return Ok(Input::Line(line));
+    }
+
+    fn edit(&mut self, prompt: &str) -> std::io::Result<Input> {
+        let mut out = std::io::stdout();
+        let mut buf: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Which history entry is shown; `history.len()` is the new line,
+        // saved in `draft` while browsing.
+        let mut pos = self.history.len();
+        let mut draft: Vec<char> = Vec::new();
+        let mut pending: Option<Key> = None;
+
+        loop {
+            let key = match pending.take() {
+                Some(key) => key,
+                None => {
+                    refresh(&mut out, prompt, &buf, cursor)?;
+                    read_key()?
+                }
+            };
+            match key {
+                Key::Char(c) => {
+                    buf.insert(cursor, c);
+                    cursor += 1;
+                },
+                Key::Tab => {
+                    buf.splice(cursor..cursor, "    ".chars());
+                    cursor += 4;
+                },
+                Key::Enter => {
+                    writeln!(out)?;
+                    return Ok(Input::Line(buf.iter().collect()));
+                },
+                Key::Ctrl('c') => {
+                    writeln!(out, "^C")?;
+                    return Ok(Input::Interrupted);
+                },
+                Key::Ctrl('d') if buf.is_empty() => {
+                    writeln!(out)?;
+                    return Ok(Input::Eof);
+                },
+                Key::Closed => {
+                    writeln!(out)?;
+                    return Ok(Input::Eof);
+                },
+                Key::Backspace | Key::Ctrl('h') if cursor > 0 => {
+                    cursor -= 1;
+                    buf.remove(cursor);
+                },
+                Key::Delete | Key::Ctrl('d') if cursor < buf.len() => {
+                    buf.remove(cursor);
+                },
+                Key::Left | Key::Ctrl('b') => cursor = cursor.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => cursor = (cursor + 1).min(buf.len()),
+                Key::Home | Key::Ctrl('a') => cursor = 0,
+                Key::End | Key::Ctrl('e') => cursor = buf.len(),
+                Key::WordLeft => cursor = word_start(&buf, cursor),
+                Key::WordRight => cursor = word_end(&buf, cursor),
+                Key::Ctrl('k') => buf.truncate(cursor),
+                Key::Ctrl('u') => {
+                    buf.drain(..cursor);
+                    cursor = 0;
+                },
+                Key::Ctrl('w') => {
+                    let start = word_start(&buf, cursor);
+                    buf.drain(start..cursor);
+                    cursor = start;
+                },
+                Key::Ctrl('l') => write!(out, "\x1b[H\x1b[2J")?,
+                Key::Up | Key::Ctrl('p') | Key::Down | Key::Ctrl('n') => {
+                    let older = matches!(key, Key::Up | Key::Ctrl('p'));
+                    let next = if older { pos.checked_sub(1) } else { Some(pos + 1).filter(|p| *p <= self.history.len()) };
+                    if let Some(next) = next {
+                        if pos == self.history.len() {
+                            draft = buf.clone();
+                        }
+                        pos = next;
+                        buf = match self.history.get(pos) {
+                            Some(line) => line.chars().collect(),
+                            None => draft.clone(),
+                        };
+                        cursor = buf.len();
+                    }
+                },
+                Key::Ctrl('r') => {
+                    let (found, next) = self.search(&mut out)?;
+                    if let Some(line) = found {
+                        buf = line.chars().collect();
+                        cursor = buf.len();
+                    }
+                    pending = next;
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // Ctrl-R: finds the newest history line containing what is typed;
+    // Ctrl-R again steps to older matches. Ctrl-G or Ctrl-C gives up and
+    // keeps the line as it was. Any other key takes the match and is then
+    // handled as usual, so Enter runs it and the arrows start editing it.
+    fn search(&self, out: &mut std::io::Stdout) -> std::io::Result<(Option<String>, Option<Key>)> {
+        let mut query = String::new();
+        let mut at = self.history.len(); // index of the current match
+        let mut failed = false; // nothing older matches the query
+        loop {
+            let line = self.history.get(at).map_or("", String::as_str);
+            let failing = if failed { "failing " } else { "" };
+            write!(out, "\r({}reverse-i-search)`{}': {}\x1b[K", failing, query, line)?;
+            out.flush()?;
+            let key = read_key()?;
+            let from = match key {
+                Key::Char(c) => {
+                    query.push(c);
+                    (at + 1).min(self.history.len())
+                },
+                Key::Backspace | Key::Ctrl('h') => {
+                    query.pop();
+                    self.history.len()
+                },
+                Key::Ctrl('r') => at,
+                Key::Ctrl('g') | Key::Ctrl('c') => return Ok((None, None)),
+                _ => return Ok((self.history.get(at).cloned(), Some(key))),
+            };
+            match find(&self.history[..from], &query) {
+                Some(i) => {
+                    at = i;
+                    failed = false;
+                },
+                None => failed = true,
+            }
+        }
+    }
+}
+
+/// Whether `source` leaves a '(' or '{' open, so the REPL should read
+/// another line before running it. Anything that does not lex is left for
+/// `eval` to report.
+pub fn unclosed(source: &str) -> bool {
+    let Ok(tokens) = mds::tokenize(source) else {
+        return false;
+    };
+    let mut depth = 0;
+    for t in tokens {
+        match t.kind {
+            TokenKind::LParen | TokenKind::LBrace => depth += 1,
+            TokenKind::RParen | TokenKind::RBrace => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    return depth > 0;
+}
+
+// Adds `line` unless it is blank or repeats the last entry, dropping the
+// oldest entry beyond `MAX_HISTORY`. Returns whether it was added.
+fn push_history(history: &mut Vec<String>, line: &str) -> bool {
+    if line.trim().is_empty() || history.last().is_some_and(|l| l == line) {
+        return false;
+    }
+    history.push(line.to_string());
+    trim_history(history);
+    return true;
+}
+
+// Keeps the newest `MAX_HISTORY` entries; returns whether any were dropped.
+fn trim_history(history: &mut Vec<String>) -> bool {
+    if history.len() <= MAX_HISTORY {
+        return false;
+    }
+    history.drain(..history.len() - MAX_HISTORY);
+    return true;
+}
+
+// The newest entry containing `query`.
+fn find(history: &[String], query: &str) -> Option<usize> {
+    return history.iter().rposition(|l| l.contains(query));
+}
+
+fn refresh(out: &mut std::io::Stdout, prompt: &str, buf: &[char], cursor: usize) -> std::io::Result<()> {
+    let line: String = buf.iter().collect();
+    write!(out, "\r{}{}\x1b[K", prompt, line)?;
+    if cursor < buf.len() {
+        write!(out, "\x1b[{}D", buf.len() - cursor)?;
+    }
+    return out.flush();
+}
+
+// Start of the word before the cursor, skipping spaces first.
+fn word_start(buf: &[char], mut i: usize) -> usize {
+    while i > 0 && !buf[i - 1].is_alphanumeric() {
+        i -= 1;
+    }
+    while i > 0 && buf[i - 1].is_alphanumeric() {
+        i -= 1;
+    }
+    return i;
+}
+
+fn word_end(buf: &[char], mut i: usize) -> usize {
+    while i < buf.len() && !buf[i].is_alphanumeric() {
+        i += 1;
+    }
+    while i < buf.len() && buf[i].is_alphanumeric() {
+        i += 1;
+    }
+    return i;
+}
+
+fn read_byte() -> std::io::Result<Option<u8>> {
+    let mut b = [0u8];
+    return match std::io::stdin().lock().read(&mut b)? {
+        0 => Ok(None),
+        _ => Ok(Some(b[0])),
+    };
+}
+
+fn read_key() -> std::io::Result<Key> {
+    let Some(b) = read_byte()? else {
+        return Ok(Key::Closed);
+    };
+    return Ok(match b {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        127 | 8 => Key::Backspace,
+        0x1b => escape()?,
+        1..=26 => Key::Ctrl((b'a' + b - 1) as char),
+        0..=0x1f => Key::Escape,
+        0x20..=0x7e => Key::Char(b as char),
+        _ => {
+            // The rest of a UTF-8 sequence; its length is in the first byte.
+            let len = if b >= 0xf0 { 4 } else if b >= 0xe0 { 3 } else { 2 };
+            let mut bytes = vec![b];
+            for _ in 1..len {
+                bytes.extend(read_byte()?);
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Escape,
+            }
+        }
+    });
+}
+
+// What follows an ESC byte: `ESC [ ... final` and `ESC O final` for
+// cursor and editing keys, `ESC b`/`ESC f` for Alt-B/Alt-F.
+fn escape() -> std::io::Result<Key> {
+    let Some(b) = read_byte()? else {
+        return Ok(Key::Closed);
+    };
+    match b {
+        b'b' => return Ok(Key::WordLeft),
+        b'f' => return Ok(Key::WordRight),
+        b'[' | b'O' => {},
+        _ => return Ok(Key::Escape),
+    }
+    let mut params = String::new();
+    loop {
+        let Some(b) = read_byte()? else {
+            return Ok(Key::Closed);
+        };
+        if (0x40..=0x7e).contains(&b) {
+            return Ok(match (b, params.as_str()) {
+                (b'A', _) => Key::Up,
+                (b'B', _) => Key::Down,
+                (b'C', "1;5" | "1;3") => Key::WordRight,
+                (b'D', "1;5" | "1;3") => Key::WordLeft,
+                (b'C', _) => Key::Right,
+                (b'D', _) => Key::Left,
+                (b'H', _) => Key::Home,
+                (b'F', _) => Key::End,
+                (b'~', "1" | "7") => Key::Home,
+                (b'~', "4" | "8") => Key::End,
+                (b'~', "3") => Key::Delete,
+                _ => Key::Escape,
+            });
+        }
+        params.push(b as char);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continuation_lines() {
+        assert!(unclosed("(1 +\n"));
+        assert!(unclosed("fn f(x) {\n  x\n"));
+        assert!(unclosed("{ (\n)\n"));
+        assert!(!unclosed("(1 +\n2)\n"));
+        assert!(!unclosed("1 +\n"));
+        assert!(!unclosed(") (\n"));
+        assert!(!unclosed("(1 $\n")); // does not lex: left for eval to report
+    }
+
+    #[test]
+    fn history_is_bounded_and_skips_repeats() {
+        let mut history = Vec::new();
+        assert!(push_history(&mut history, "1 + 1"));
+        assert!(!push_history(&mut history, "1 + 1"));
+        assert!(!push_history(&mut history, "   "));
+        assert!(push_history(&mut history, "x = 2"));
+        assert!(push_history(&mut history, "1 + 1"));
+        assert_eq!(history, ["1 + 1", "x = 2", "1 + 1"]);
+
+        let mut history: Vec<String> = (0..MAX_HISTORY + 5).map(|i| i.to_string()).collect();
+        assert!(trim_history(&mut history));
+        assert_eq!(history.len(), MAX_HISTORY);
+        assert_eq!(history[0], "5");
+        assert!(!trim_history(&mut history));
+        push_history(&mut history, "new");
+        assert_eq!(history.len(), MAX_HISTORY);
+        assert_eq!((history[0].as_str(), history.last().unwrap().as_str()), ("6", "new"));
+    }
+
+    #[test]
+    fn word_movement() {
+        let buf: Vec<char> = "x = foo(bar1, 2)".chars().collect();
+        assert_eq!(word_start(&buf, buf.len()), 14);
+        assert_eq!(word_start(&buf, 12), 8);
+        assert_eq!(word_start(&buf, 8), 4);
+        assert_eq!(word_start(&buf, 1), 0);
+        assert_eq!(word_start(&buf, 0), 0);
+        assert_eq!(word_end(&buf, 0), 1);
+        assert_eq!(word_end(&buf, 1), 7);
+        assert_eq!(word_end(&buf, 7), 12);
+        assert_eq!(word_end(&buf, 15), buf.len());
+    }
+
+    #[test]
+    fn reverse_search() {
+        let history: Vec<String> = ["x = 1", "y = 2", "x + y", "z"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(find(&history, "x"), Some(2));
+        assert_eq!(find(&history[..2], "x"), Some(0));
+        assert_eq!(find(&history, "= 2"), Some(1));
+        assert_eq!(find(&history, ""), Some(3));
+        assert_eq!(find(&history, "w"), None);
+        assert_eq!(find(&[], "x"), None);
+    }
+}
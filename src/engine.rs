@@ -0,0 +1,197 @@
+use std::rc::Rc;
+
+use crate::compiler::{ByteCode, ByteCodes};
+use crate::decimal::{Context, Rounding, MAX_PRECISION};
+use crate::lexer::{Diagnostic, Lexer, Token};
+use crate::natives::{Native, Type};
+use crate::value::{RuntimeError, Value};
+use crate::vm::{Env, VM};
+use crate::{compile, parse};
+
+/// Why a call to [`Engine::eval`] failed.
+#[derive(Clone, Debug)]
+pub enum Error {
+    Syntax(Diagnostic), // rejected by the lexer or parser, nothing ran
+    Runtime(RuntimeError),
+}
+
+impl Error {
+    /// Formats the error for display; syntax errors quote `source`.
+    pub fn render(&self, source: &str) -> String {
+        match self {
+            Error::Syntax(d) => d.render(source),
+            Error::Runtime(e) => format!("Error: {}", e),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Syntax(d) => write!(f, "{} at {}:{}", d.message, d.span.line, d.span.col),
+            Error::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Diagnostic> for Error {
+    fn from(d: Diagnostic) -> Error {
+        return Error::Syntax(d);
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Error {
+        return Error::Runtime(e);
+    }
+}
+
+/// An interpreter session: globals, functions and the result history
+/// persist from one `eval` to the next.
+///
+/// ```
+/// use mds::{Engine, Type, Value};
+///
+/// let mut engine = Engine::new();
+/// engine.register("hyp", &[Type::Float, Type::Float], |a| {
+///     Ok(Value::Float(a[0].as_f64().unwrap().hypot(a[1].as_f64().unwrap())))
+/// });
+/// engine.set_global("x", 3);
+/// assert_eq!(engine.eval("hyp(x, 4)").ok(), Some(Value::Float(5.0)));
+/// ```
+pub struct Engine {
+    env: Env,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        return Engine::new();
+    }
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        return Engine { env: Env::new() };
+    }
+
+    /// Runs a program and returns the value of its last statement. Results
+    /// other than `()` are appended to the history that `_`, `ans` and `_N`
+    /// refer to.
+    ///
+    /// Running a program does not recurse, but parsing it does: see
+    /// [`parse`](crate::parse) for the stack that nested input takes.
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        let code = compile(&parse(self.tokenize(source)?)?)?;
+        return self.execute(VM::new(code));
+    }
+
+    /// Like [`Engine::eval`], but calls `f` after every instruction the VM
+    /// executes with that instruction and the stack it left, top last.
+    pub fn trace<F>(&mut self, source: &str, f: F) -> Result<Value, Error>
+    where
+        F: FnMut(&ByteCode, &[Value]) + 'static,
+    {
+        let mut vm = VM::new(compile(&parse(self.tokenize(source)?)?)?);
+        vm.set_trace(f);
+        return self.execute(vm);
+    }
+
+    fn execute(&mut self, mut vm: VM) -> Result<Value, Error> {
+        let v = vm.run(&mut self.env)?;
+        if v != Value::Unit {
+            self.env.record(v.clone());
+        }
+        return Ok(v);
+    }
+
+    /// Splits source into tokens the way `eval` would in this session;
+    /// in decimal mode `0.1` is a Decimal literal.
+    pub fn tokenize(&self, source: &str) -> Result<Vec<Token>, Error> {
+        return Ok(Lexer::new(source.to_string()).decimal_mode(self.env.decimal).next_token()?);
+    }
+
+    /// Runs compiled code against this session's globals. Unlike `eval`
+    /// the result is not added to the history.
+    pub fn run(&mut self, code: ByteCodes) -> Result<Value, Error> {
+        return Ok(VM::new(code).run(&mut self.env)?);
+    }
+
+    /// Makes a Rust closure callable by `name`. Arguments are checked
+    /// against `params` before `f` runs, so `f` may rely on their types;
+    /// a Float parameter receives Int arguments converted to Float.
+    /// Replaces any earlier native of the same name, built-ins included.
+    pub fn register<F>(&mut self, name: &str, params: &[Type], f: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        self.env.natives.insert(name.to_string(), Rc::new(Native::new(name, params, f)));
+    }
+
+    /// Like [`Engine::register`], but the last parameter type repeats for
+    /// any number of extra arguments.
+    pub fn register_variadic<F>(&mut self, name: &str, params: &[Type], f: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        assert!(!params.is_empty(), "a variadic function needs at least one parameter type");
+        self.env.natives.insert(name.to_string(), Rc::new(Native::variadic(name, params, f)));
+    }
+
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        self.env.globals.insert(name.to_string(), value.into());
+    }
+
+    /// The value of a global assigned by a script or `set_global`.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        return self.env.globals.get(name).cloned();
+    }
+
+    /// All globals, functions defined by scripts included, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<(String, Value)> = self.env.globals.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        return globals;
+    }
+
+    /// In exact mode dividing integers gives a fraction rather than
+    /// truncating, so `1/3 + 1/3 + 1/3 == 1`. Off by default.
+    pub fn set_exact(&mut self, on: bool) {
+        self.env.exact = on;
+    }
+
+    pub fn exact(&self) -> bool {
+        return self.env.exact;
+    }
+
+    /// In decimal mode `0.1` is a [`Decimal`](crate::Decimal) rather than
+    /// a Float, and dividing integers gives a Decimal rounded to the
+    /// context. Exact mode takes precedence. Off by default; a `d` suffix
+    /// such as `0.1d` gives a Decimal either way.
+    pub fn set_decimal(&mut self, on: bool) {
+        self.env.decimal = on;
+    }
+
+    pub fn decimal(&self) -> bool {
+        return self.env.decimal;
+    }
+
+    /// Significant digits kept by Decimal results, clamped to between 1
+    /// and [`MAX_PRECISION`].
+    pub fn set_precision(&mut self, digits: u32) {
+        self.env.context.precision = digits.clamp(1, MAX_PRECISION);
+    }
+
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.env.context.rounding = rounding;
+    }
+
+    /// The precision and rounding Decimal results are cut back to.
+    pub fn context(&self) -> Context {
+        return self.env.context;
+    }
+
+    /// Nested calls allowed before a script fails with `RecursionLimit`.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.env.max_depth = depth;
+    }
+}
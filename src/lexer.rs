@@ -0,0 +1,807 @@
+use crate::bigint::BigInt;
+use crate::decimal::Decimal;
+use crate::units::Unit;
+
+/// Where a token or expression sits in the source text.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Span {
+    /// Byte offset of the first character.
+    pub start: usize,
+    /// Byte offset one past the last character.
+    pub end: usize,
+    /// 1-based line of `start`.
+    pub line: usize,
+    /// 1-based column (in chars) of `start`.
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, col: usize) -> Self {
+        Span {
+            start,
+            end,
+            line,
+            col,
+        }
+    }
+
+    /// Span running from the start of `self` to the end of `other`.
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            col: self.col,
+        }
+    }
+}
+
+/// A syntax error reported by the lexer or the parser.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// Renders the message followed by the offending source line with the
+    /// span underlined, e.g.
+    ///
+    /// ```text
+    /// error: Expected ')'
+    ///  --> 1:7
+    ///   |
+    /// 1 | (1 + 2
+    ///   |       ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let line_no = self.span.line.max(1);
+        let line = source.lines().nth(line_no - 1).unwrap_or("");
+        let gutter = " ".repeat(line_no.to_string().len());
+
+        // Keep tabs in the padding so the carets line up with the source.
+        let pad: String = line
+            .chars()
+            .take(self.span.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let line_start = source[..self.span.start.min(source.len())]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let line_end = line_start + line.len();
+        let end = self.span.end.min(line_end).max(self.span.start);
+        let width = source
+            .get(self.span.start..end)
+            .map(|s| s.chars().count())
+            .unwrap_or(0)
+            .max(1);
+
+        return format!(
+            "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            line_no,
+            self.span.col,
+            gutter,
+            line_no,
+            line,
+            gutter,
+            pad,
+            "^".repeat(width)
+        );
+    }
+}
+
+/// The kind of a token. Literals and identifiers carry their value;
+/// keywords and operators are named after what they spell.
+#[derive(Clone, Debug)]
+#[derive(PartialEq)]
+pub enum TokenKind {
+    /// End of input, always the last token.
+    EOF,
+    Int(i64),
+    /// An integer literal too large for `i64`.
+    BigInt(BigInt),
+    /// A literal with a `d` suffix, or any non-integer literal in decimal mode.
+    Decimal(Decimal),
+    Float(f64),
+    /// An imaginary literal such as `4i`, holding the 4.
+    Imag(f64),
+    /// The unit written after a number, as in `9.81 m/s^2`.
+    Unit(Unit),
+    Ident(String),
+    True,
+    False,
+    If,
+    Then,
+    Else,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
+    Fn,
+
+    Assign,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Percent,
+    SlashSlash,
+
+    Amp,
+    Pipe,
+    Xor,
+    Tilde,
+    Shl,
+    Shr,
+
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    AndAnd,
+    OrOr,
+    Not,
+    Question,
+    Colon,
+    Semicolon,
+    /// A line break that can end a statement: outside of parentheses.
+    Newline,
+    Comma,
+    DotDot,
+    DotDotEq,
+
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+}
+
+/// A token together with the source it was lexed from.
+#[derive(Clone, Debug)]
+#[derive(PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+pub fn to_string(t: &TokenKind) -> String {
+    match t {
+        TokenKind::EOF => "EOF".to_string(),
+        TokenKind::Int(i) => i.to_string(),
+        TokenKind::BigInt(i) => i.to_string(),
+        TokenKind::Decimal(d) => format!("{}d", d),
+        TokenKind::Float(f) => f.to_string(),
+        TokenKind::Imag(f) => format!("{}i", f),
+        TokenKind::Unit(u) => u.to_string(),
+        TokenKind::Ident(s) => s.clone(),
+        TokenKind::True => "true".to_string(),
+        TokenKind::False => "false".to_string(),
+        TokenKind::If => "if".to_string(),
+        TokenKind::Then => "then".to_string(),
+        TokenKind::Else => "else".to_string(),
+        TokenKind::While => "while".to_string(),
+        TokenKind::For => "for".to_string(),
+        TokenKind::In => "in".to_string(),
+        TokenKind::Break => "break".to_string(),
+        TokenKind::Continue => "continue".to_string(),
+        TokenKind::Fn => "fn".to_string(),
+
+        TokenKind::Assign => "=".to_string(),
+
+        TokenKind::Add => "+".to_string(),
+        TokenKind::Sub => "-".to_string(),
+        TokenKind::Mul => "*".to_string(),
+        TokenKind::Div => "/".to_string(),
+        TokenKind::Pow => "^".to_string(),
+        TokenKind::Percent => "%".to_string(),
+        TokenKind::SlashSlash => "//".to_string(),
+
+        TokenKind::Amp => "&".to_string(),
+        TokenKind::Pipe => "|".to_string(),
+        TokenKind::Xor => "xor".to_string(),
+        TokenKind::Tilde => "~".to_string(),
+        TokenKind::Shl => "<<".to_string(),
+        TokenKind::Shr => ">>".to_string(),
+
+        TokenKind::Eq => "==".to_string(),
+        TokenKind::Ne => "!=".to_string(),
+        TokenKind::Lt => "<".to_string(),
+        TokenKind::Le => "<=".to_string(),
+        TokenKind::Gt => ">".to_string(),
+        TokenKind::Ge => ">=".to_string(),
+        TokenKind::AndAnd => "&&".to_string(),
+        TokenKind::OrOr => "||".to_string(),
+        TokenKind::Not => "!".to_string(),
+        TokenKind::Question => "?".to_string(),
+        TokenKind::Colon => ":".to_string(),
+        TokenKind::Semicolon => ";".to_string(),
+        TokenKind::Newline => "\\n".to_string(),
+        TokenKind::Comma => ",".to_string(),
+        TokenKind::DotDot => "..".to_string(),
+        TokenKind::DotDotEq => "..=".to_string(),
+
+        TokenKind::LParen => "(".to_string(),
+        TokenKind::RParen => ")".to_string(),
+        TokenKind::LBrace => "{".to_string(),
+        TokenKind::RBrace => "}".to_string(),
+    }
+}
+
+// Cursor over the input: `position` is a byte offset that only ever moves
+// forward by whole UTF-8 characters, so every step is O(1).
+pub struct Lexer {
+    input: String,
+    position: usize,
+    decimal: bool, // read `0.1` as a Decimal rather than a Float
+
+    line: usize,
+    col: usize,
+}
+
+impl Lexer {
+    pub fn new(input: String) -> Self {
+        Lexer {
+            input,
+            position: 0,
+            decimal: false,
+
+            line: 1,
+            col: 1,
+        }
+    }
+
+    // Session-wide decimal mode: literals with a point or an exponent
+    // become Decimals even without the `d` suffix.
+    pub fn decimal_mode(mut self, on: bool) -> Self {
+        self.decimal = on;
+        return self;
+    }
+
+    fn peek(&self) -> Option<char> {
+        return self.input[self.position..].chars().next();
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        return self.input[self.position..].chars().nth(n);
+    }
+
+    fn advance(&mut self, c: char) {
+        self.position += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+    }
+
+    fn here(&self) -> Span {
+        return Span::new(self.position, self.position, self.line, self.col);
+    }
+
+    fn single(&mut self, kind: TokenKind, c: char) -> Token {
+        let start = self.here();
+        self.advance(c);
+        return Token {
+            kind,
+            span: start.to(self.here()),
+        };
+    }
+
+    // Operator of `len` ASCII characters starting at the current position.
+    fn operator(&mut self, kind: TokenKind, len: usize) -> Token {
+        let start = self.here();
+        for _ in 0..len {
+            let c = self.peek().unwrap();
+            self.advance(c);
+        }
+        return Token {
+            kind,
+            span: start.to(self.here()),
+        };
+    }
+
+    pub fn next_token(&mut self) -> Result<Vec<Token>, Diagnostic> {
+        let mut v: Vec<Token> = Vec::new();
+        // Open brackets; inside `(` a line break is only whitespace
+        let mut brackets: Vec<char> = Vec::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' | '.' if c != '.' || matches!(self.peek_nth(1), Some('0'..='9')) => {
+                    let num = self.number();
+                    match num {
+                        Ok(n) => {
+                            v.push(n);
+                            if let Some(u) = self.unit()? {
+                                v.push(u);
+                            }
+                            continue;
+                        },
+                        Err(e) => {
+                            return Err(e);
+                        }
+                    }
+                },
+                c if c.is_alphabetic() || c == '_' => {
+                    v.push(self.ident());
+                    continue;
+                },
+                '=' if self.peek_nth(1) == Some('=') => {
+                    v.push(self.operator(TokenKind::Eq, 2));
+                },
+                '!' if self.peek_nth(1) == Some('=') => {
+                    v.push(self.operator(TokenKind::Ne, 2));
+                },
+                '<' if self.peek_nth(1) == Some('=') => {
+                    v.push(self.operator(TokenKind::Le, 2));
+                },
+                '>' if self.peek_nth(1) == Some('=') => {
+                    v.push(self.operator(TokenKind::Ge, 2));
+                },
+                '&' if self.peek_nth(1) == Some('&') => {
+                    v.push(self.operator(TokenKind::AndAnd, 2));
+                },
+                '|' if self.peek_nth(1) == Some('|') => {
+                    v.push(self.operator(TokenKind::OrOr, 2));
+                },
+                '/' if self.peek_nth(1) == Some('/') => {
+                    v.push(self.operator(TokenKind::SlashSlash, 2));
+                },
+                '<' if self.peek_nth(1) == Some('<') => {
+                    v.push(self.operator(TokenKind::Shl, 2));
+                },
+                '>' if self.peek_nth(1) == Some('>') => {
+                    v.push(self.operator(TokenKind::Shr, 2));
+                },
+                '&' => {
+                    v.push(self.single(TokenKind::Amp, c));
+                },
+                '|' => {
+                    v.push(self.single(TokenKind::Pipe, c));
+                },
+                '~' => {
+                    v.push(self.single(TokenKind::Tilde, c));
+                },
+                '%' => {
+                    v.push(self.single(TokenKind::Percent, c));
+                },
+                '.' if self.peek_nth(1) == Some('.') => {
+                    if self.peek_nth(2) == Some('=') {
+                        v.push(self.operator(TokenKind::DotDotEq, 3));
+                    } else {
+                        v.push(self.operator(TokenKind::DotDot, 2));
+                    }
+                },
+                ';' => {
+                    v.push(self.single(TokenKind::Semicolon, c));
+                },
+                ',' => {
+                    v.push(self.single(TokenKind::Comma, c));
+                },
+                '{' => {
+                    brackets.push(c);
+                    v.push(self.single(TokenKind::LBrace, c));
+                },
+                '}' => {
+                    brackets.pop();
+                    v.push(self.single(TokenKind::RBrace, c));
+                },
+                '?' => {
+                    v.push(self.single(TokenKind::Question, c));
+                },
+                ':' => {
+                    v.push(self.single(TokenKind::Colon, c));
+                },
+                '=' => {
+                    v.push(self.single(TokenKind::Assign, c));
+                },
+                '!' => {
+                    v.push(self.single(TokenKind::Not, c));
+                },
+                '<' => {
+                    v.push(self.single(TokenKind::Lt, c));
+                },
+                '>' => {
+                    v.push(self.single(TokenKind::Gt, c));
+                },
+                '+' => {
+                    v.push(self.single(TokenKind::Add, c));
+                },
+                '-' => {
+                    v.push(self.single(TokenKind::Sub, c));
+                },
+                '*' => {
+                    v.push(self.single(TokenKind::Mul, c));
+                },
+                '/' => {
+                    v.push(self.single(TokenKind::Div, c));
+                },
+                '^' => {
+                    v.push(self.single(TokenKind::Pow, c));
+                }
+                '(' => {
+                    brackets.push(c);
+                    v.push(self.single(TokenKind::LParen, c));
+                },
+                ')' => {
+                    brackets.pop();
+                    v.push(self.single(TokenKind::RParen, c));
+                },
+                '\n' if brackets.last() != Some(&'(') => {
+                    // Blank lines and leading breaks add nothing
+                    if matches!(v.last(), None | Some(Token { kind: TokenKind::Newline, .. })) {
+                        self.advance(c);
+                        continue;
+                    }
+                    v.push(self.single(TokenKind::Newline, c));
+                },
+                c if c.is_whitespace() => {
+                    self.advance(c);
+                    continue;
+                },
+                _ => {
+                    let start = self.here();
+                    self.advance(c);
+                    return Err(Diagnostic::new(
+                        format!("Unexpected character: {:?}", c),
+                        start.to(self.here()),
+                    ));
+                }
+            }
+        }
+
+        v.push(Token {
+            kind: TokenKind::EOF,
+            span: self.here(),
+        });
+        return Ok(v);
+    }
+
+    // A unit after a number: unit names joined by `*` and `/`, each with
+    // an optional whole power, as in `kg*m/s^2`. Outside parentheses the
+    // names and operators are written without spaces, so in `6 m / s` the
+    // `s` is left for a variable; `6 (m / s)` spells the unit out. A name
+    // that is not a unit is left alone, so `2 x` still reads as a number
+    // and a name.
+    fn unit(&mut self) -> Result<Option<Token>, Diagnostic> {
+        let mark = (self.position, self.line, self.col);
+        self.skip_blanks();
+        let start = self.here();
+        let unit = if self.peek() == Some('(') {
+            self.advance('(');
+            self.skip_blanks();
+            let unit = self.compound(true)?;
+            self.skip_blanks();
+            match unit {
+                Some(u) if self.peek() == Some(')') => {
+                    self.advance(')');
+                    Some(u)
+                },
+                _ => None,
+            }
+        } else {
+            self.compound(false)?
+        };
+        let Some(unit) = unit else {
+            (self.position, self.line, self.col) = mark;
+            return Ok(None);
+        };
+        return Ok(Some(Token { kind: TokenKind::Unit(unit), span: start.to(self.here()) }));
+    }
+
+    // Unit names joined by `*` and `/`, with blanks around the operators
+    // only when `spaced`; stops before anything that does not continue it.
+    fn compound(&mut self, spaced: bool) -> Result<Option<Unit>, Diagnostic> {
+        let mut unit: Option<Unit> = None;
+        loop {
+            let mark = (self.position, self.line, self.col);
+            if spaced && unit.is_some() {
+                self.skip_blanks();
+            }
+            let op = match self.peek() {
+                _ if unit.is_none() => '*',
+                Some(c @ ('*' | '/')) if !matches!(self.peek_nth(1), Some('*' | '/')) => {
+                    self.advance(c);
+                    if spaced {
+                        self.skip_blanks();
+                    }
+                    c
+                },
+                _ => {
+                    (self.position, self.line, self.col) = mark;
+                    break;
+                }
+            };
+            let name_start = self.position;
+            let factor_start = self.here();
+            while let Some(c) = self.peek() {
+                if !(c.is_alphabetic() || c == '_' || (c.is_ascii_digit() && self.position > name_start)) {
+                    break;
+                }
+                self.advance(c);
+            }
+            let Some(factor) = Unit::lookup(&self.input[name_start..self.position]) else {
+                (self.position, self.line, self.col) = mark;
+                break;
+            };
+            let factor = match self.power()? {
+                Some(n) => factor.powi(n),
+                None => Some(factor),
+            };
+            let product = match (unit, factor) {
+                (None, factor) => factor,
+                (Some(u), Some(f)) if op == '*' => u.mul(&f),
+                (Some(u), Some(f)) => u.div(&f),
+                (Some(_), None) => None,
+            };
+            let Some(product) = product else {
+                return Err(Diagnostic::new("Unit power is too large", factor_start.to(self.here())));
+            };
+            unit = Some(product);
+        }
+        return Ok(unit);
+    }
+
+    fn skip_blanks(&mut self) {
+        while let Some(c @ (' ' | '\t')) = self.peek() {
+            self.advance(c);
+        }
+    }
+
+    // The `^2` or `^-1` of a unit; a `^` without digits is left alone.
+    fn power(&mut self) -> Result<Option<i32>, Diagnostic> {
+        if self.peek() != Some('^') {
+            return Ok(None);
+        }
+        let neg = self.peek_nth(1) == Some('-');
+        let first = if neg { 2 } else { 1 };
+        if !matches!(self.peek_nth(first), Some('0'..='9')) {
+            return Ok(None);
+        }
+        let start = self.here();
+        let mut digits = String::new();
+        for _ in 0..first {
+            let c = self.peek().unwrap();
+            self.advance(c);
+        }
+        while let Some(c @ '0'..='9') = self.peek() {
+            digits.push(c);
+            self.advance(c);
+        }
+        let n = digits.parse::<i32>().map_err(|_| Diagnostic::new("Unit power is too large", start.to(self.here())))?;
+        return Ok(Some(if neg { -n } else { n }));
+    }
+
+    fn ident(&mut self) -> Token {
+        let start = self.here();
+
+        while let Some(c) = self.peek() {
+            if !(c.is_alphanumeric() || c == '_') {
+                break;
+            }
+            self.advance(c);
+        }
+
+        let kind = match &self.input[start.start..self.position] {
+            "true" => TokenKind::True,
+            "false" => TokenKind::False,
+            "xor" => TokenKind::Xor,
+            "if" => TokenKind::If,
+            "then" => TokenKind::Then,
+            "else" => TokenKind::Else,
+            "while" => TokenKind::While,
+            "for" => TokenKind::For,
+            "in" => TokenKind::In,
+            "break" => TokenKind::Break,
+            "continue" => TokenKind::Continue,
+            "fn" => TokenKind::Fn,
+            name => TokenKind::Ident(name.to_string()),
+        };
+        return Token {
+            kind,
+            span: start.to(self.here()),
+        };
+    }
+
+    fn number(&mut self) -> Result<Token, Diagnostic> {
+        // 0x.. | 0o.. | 0b..
+        // digits ['.' digits] [('e'|'E') ['+'|'-'] digits]
+        // '.' digits [('e'|'E') ['+'|'-'] digits]
+
+        let start = self.here();
+        if self.peek() == Some('0') {
+            let radix = match self.peek_nth(1) {
+                Some('x') | Some('X') => Some((16, "hexadecimal")),
+                Some('o') | Some('O') => Some((8, "octal")),
+                Some('b') | Some('B') => Some((2, "binary")),
+                _ => None,
+            };
+            if let Some((radix, name)) = radix {
+                return self.radix_number(start, radix, name);
+            }
+        }
+
+        let mut num = "".to_string();
+        let mut is_float = false;
+        if self.peek() != Some('.') {
+            self.digits(&mut num)?;
+        }
+
+        // `1..10` is a range, not a malformed float
+        if self.peek() == Some('.') && self.peek_nth(1) != Some('.') {
+            is_float = true;
+            let dot = self.here();
+            self.advance('.');
+            num.push('.');
+            if !matches!(self.peek(), Some('0'..='9')) {
+                return Err(Diagnostic::new("Expected digits after the decimal point", dot.to(self.here())));
+            }
+            self.digits(&mut num)?;
+
+            if self.peek() == Some('.') && matches!(self.peek_nth(1), Some('0'..='9')) {
+                let dot = self.here();
+                self.advance('.');
+                return Err(Diagnostic::new("Too many decimal points", dot.to(self.here())));
+            }
+        }
+
+        if let Some(e) = self.peek()
+            && (e == 'e' || e == 'E')
+        {
+            let digit_at = match self.peek_nth(1) {
+                Some('+') | Some('-') => 2,
+                _ => 1,
+            };
+            let exp_start = self.here();
+            match self.peek_nth(digit_at) {
+                Some('0'..='9') => {
+                    is_float = true;
+                    num.push('e');
+                    self.advance(e);
+                    if digit_at == 2 {
+                        let sign = self.peek().unwrap();
+                        num.push(sign);
+                        self.advance(sign);
+                    }
+                    self.digits(&mut num)?;
+                },
+                Some(c) if digit_at == 1 && (c.is_alphanumeric() || c == '_') => {
+                    // `2em` is the number 2 followed by the name `em`
+                },
+                _ => {
+                    self.advance(e);
+                    return Err(Diagnostic::new("Expected digits in the exponent", exp_start.to(self.here())));
+                }
+            }
+        }
+
+        // `0.1d` is a decimal literal and `4i` an imaginary one; `2day` is
+        // 2 followed by a name
+        let suffix = match self.peek() {
+            Some(c @ ('d' | 'i')) if !self.peek_nth(1).is_some_and(|c| c.is_alphanumeric() || c == '_') => {
+                self.advance(c);
+                Some(c)
+            },
+            _ => None,
+        };
+
+        let span = start.to(self.here());
+        if suffix == Some('i') {
+            let f = num.parse::<f64>().map_err(|_| Diagnostic::new("Invalid floating point", span))?;
+            return Ok(Token { kind: TokenKind::Imag(f), span });
+        }
+        if suffix == Some('d') || (is_float && self.decimal) {
+            let d = Decimal::parse(&num).ok_or(Diagnostic::new("Invalid decimal literal", span))?;
+            return Ok(Token { kind: TokenKind::Decimal(d), span });
+        }
+        if !is_float {
+            match num.parse::<i64>() {
+                Ok(n) => {
+                    return Ok(Token { kind: TokenKind::Int(n), span });
+                },
+                Err(_) => {
+                    let n = BigInt::parse(&num, 10).ok_or(Diagnostic::new("Invalid integer literal", span))?;
+                    return Ok(Token { kind: TokenKind::BigInt(n), span });
+                }
+            }
+        } else {
+            match num.parse::<f64>() {
+                Ok(n) => {
+                    return Ok(Token { kind: TokenKind::Float(n), span });
+                },
+                Err(_) => {
+                    return Err(Diagnostic::new("Invalid floating point", span));
+                }
+            }
+        }
+    }
+
+    // Reads decimal digits into `out`, dropping `_` separators. A separator
+    // has to sit between two digits.
+    fn digits(&mut self, out: &mut String) -> Result<(), Diagnostic> {
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' => {
+                    out.push(c);
+                    self.advance(c);
+                },
+                '_' => {
+                    let at = self.here();
+                    self.advance(c);
+                    if out.is_empty() || out.ends_with(['.', 'e', '+', '-']) || !matches!(self.peek(), Some('0'..='9')) {
+                        return Err(Diagnostic::new("Digit separator '_' must be between digits", at.to(self.here())));
+                    }
+                },
+                _ => {
+                    break;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn radix_number(&mut self, start: Span, radix: u32, name: &str) -> Result<Token, Diagnostic> {
+        self.advance('0');
+        let prefix = self.peek().unwrap();
+        self.advance(prefix);
+
+        let mut num = "".to_string();
+        let mut last_sep = false;
+        while let Some(c) = self.peek() {
+            let at = self.here();
+            if c == '_' {
+                self.advance(c);
+                if num.is_empty() || last_sep {
+                    return Err(Diagnostic::new("Digit separator '_' must be between digits", at.to(self.here())));
+                }
+                last_sep = true;
+                continue;
+            }
+            if !c.is_alphanumeric() {
+                break;
+            }
+            self.advance(c);
+            if !c.is_digit(radix) {
+                return Err(Diagnostic::new(
+                    format!("Invalid digit {:?} in {} literal", c, name),
+                    at.to(self.here()),
+                ));
+            }
+            num.push(c);
+            last_sep = false;
+        }
+
+        let span = start.to(self.here());
+        if num.is_empty() {
+            return Err(Diagnostic::new(format!("Expected digits after '0{}'", prefix), span));
+        }
+        if last_sep {
+            return Err(Diagnostic::new("Digit separator '_' must be between digits", span));
+        }
+        match i64::from_str_radix(&num, radix) {
+            Ok(n) => {
+                return Ok(Token { kind: TokenKind::Int(n), span });
+            },
+            Err(_) => {
+                let n = BigInt::parse(&num, radix).ok_or(Diagnostic::new("Invalid integer literal", span))?;
+                return Ok(Token { kind: TokenKind::BigInt(n), span });
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! A small expression language: an interactive calculator with variables,
+//! loops and functions.
+//!
+//! Source text goes through four stages, each available on its own:
+//!
+//! ```
+//! let tokens = mds::tokenize("fn sq(x) = x * x").unwrap();
+//! let ast = mds::parse(tokens).unwrap();
+//! let code = mds::compile(&ast).unwrap();
+//! assert_eq!(mds::run(code).unwrap(), mds::Value::Unit);
+//!
+//! assert_eq!(mds::eval("2 ^ 10").unwrap(), mds::Value::Int(1024));
+//! ```
+//!
+//! These functions each start from a fresh environment. An [`Engine`]
+//! keeps globals and results between evaluations and lets the host add
+//! its own functions.
+
+#![allow(clippy::needless_return, clippy::upper_case_acronyms)]
+
+mod bigint;
+mod compiler;
+mod complex;
+mod decimal;
+mod engine;
+mod lexer;
+mod natives;
+mod parser;
+mod rational;
+mod units;
+mod value;
+mod vm;
+
+#[cfg(test)]
+mod tests;
+
+pub use bigint::BigInt;
+pub use compiler::{ByteCode, ByteCodes, Function};
+pub use complex::Complex;
+pub use decimal::{Context, Decimal, Rounding, MAX_PRECISION};
+pub use engine::{Engine, Error};
+pub use lexer::{Diagnostic, Span, Token, TokenKind};
+pub use natives::{Arity, Native, NativeFn, Type};
+pub use rational::Rational;
+pub use units::{Quantity, Unit};
+pub use parser::{BinOp, Expr, ExprKind, LogicOp, UnaryOp};
+pub use value::{RuntimeError, Value};
+
+/// Splits source text into tokens. The last token is always `EOF`.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Error> {
+    return Ok(lexer::Lexer::new(source.to_string()).next_token()?);
+}
+
+/// Parses the tokens of a program, one or more statements separated by
+/// `;` or line breaks, into a syntax tree.
+///
+/// Parsing, and compiling the tree, recurse once per level of nesting;
+/// past 128 levels parsing fails with a diagnostic instead. The deepest
+/// input it accepts needs about 5 MB of stack in a debug build and 1 MB
+/// in a release build: enough for a main thread, but more than a spawned
+/// thread gets by default.
+pub fn parse(tokens: Vec<Token>) -> Result<Expr, Error> {
+    return Ok(parser::Perser::new(tokens).parser()?);
+}
+
+/// Compiles a syntax tree to bytecode. Every tree the parser produces has
+/// a translation; a tree built by hand can fail with a syntax error, for
+/// example for a `break` outside a loop.
+pub fn compile(ast: &Expr) -> Result<ByteCodes, Error> {
+    return Ok(compiler::Dis::new().dis(ast)?);
+}
+
+/// Runs bytecode with no globals defined beyond the built-in library.
+pub fn run(code: ByteCodes) -> Result<Value, Error> {
+    return Engine::new().run(code);
+}
+
+/// Evaluates source text in a fresh environment. Like [`parse`] it needs
+/// a few megabytes of stack for deeply nested input.
+pub fn eval(source: &str) -> Result<Value, Error> {
+    return Engine::new().eval(source);
+}
@@ -0,0 +1,346 @@
+use std::rc::Rc;
+
+use crate::bigint::{BigInt, MAX_BITS};
+use crate::complex::Complex;
+use crate::decimal::Rounding;
+use crate::rational::Rational;
+use crate::value::{RuntimeError, Value};
+use crate::vm::VM;
+
+/// Body of a function implemented in Rust. It only sees arguments that
+/// already match the declared parameter types.
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, RuntimeError>;
+
+/// Parameter type in the signature of a native function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Type {
+    Int,
+    Float, // an Int argument is converted to Float
+    Complex, // a Float or Complex; other numbers are converted to Float
+    Number, // any numeric value, passed through unchanged
+    Bool,
+    Any,
+}
+
+impl Type {
+    fn check(&self, name: &str, v: &Value) -> Result<Value, RuntimeError> {
+        match (self, v) {
+            (Type::Float | Type::Complex, Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Decimal(_)) => {
+                Ok(Value::Float(float(v)))
+            },
+            (Type::Int | Type::Number, Value::Int(_) | Value::BigInt(_))
+            | (Type::Float | Type::Complex | Type::Number, Value::Float(_))
+            | (Type::Complex | Type::Number, Value::Complex(_))
+            | (Type::Number, Value::Rational(_) | Value::Decimal(_))
+            | (Type::Bool, Value::Bool(_))
+            | (Type::Any, _) => Ok(v.clone()),
+            _ => Err(RuntimeError::TypeError(format!(
+                "{} expects {}, found {}",
+                name,
+                self.describe(),
+                v.type_name()
+            ))),
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Type::Int => "an Int",
+            Type::Float | Type::Complex | Type::Number => "a number",
+            Type::Bool => "a Bool",
+            Type::Any => "a value",
+        }
+    }
+}
+
+/// How many arguments a native function takes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    fn accepts(&self, n: usize) -> bool {
+        match self {
+            Arity::Exact(a) => n == *a,
+            Arity::AtLeast(a) => n >= *a,
+        }
+    }
+
+    fn expected(&self) -> usize {
+        match self {
+            Arity::Exact(a) | Arity::AtLeast(a) => *a,
+        }
+    }
+}
+
+/// A function implemented in Rust, either built in or registered by the host.
+pub struct Native {
+    pub name: String,
+    pub arity: Arity,
+    params: Vec<Type>, // a variadic function repeats the last type
+    func: Box<NativeFn>,
+}
+
+impl Native {
+    /// Takes exactly one argument per entry of `params`.
+    pub fn new<F>(name: &str, params: &[Type], func: F) -> Native
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        return Native {
+            name: name.to_string(),
+            arity: Arity::Exact(params.len()),
+            params: params.to_vec(),
+            func: Box::new(func),
+        };
+    }
+
+    /// Takes `params.len()` or more arguments; `params` must not be empty.
+    pub fn variadic<F>(name: &str, params: &[Type], func: F) -> Native
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let mut n = Native::new(name, params, func);
+        n.arity = Arity::AtLeast(params.len());
+        return n;
+    }
+
+    /// Checks `args` against the signature, then runs the function.
+    pub fn call(&self, mut args: Vec<Value>) -> Result<Value, RuntimeError> {
+        if !self.arity.accepts(args.len()) {
+            return Err(RuntimeError::ArityMismatch {
+                name: self.name.clone(),
+                expected: self.arity.expected(),
+                found: args.len(),
+            });
+        }
+        for (i, arg) in args.iter_mut().enumerate() {
+            if let Some(t) = self.params.get(i).or(self.params.last()) {
+                *arg = t.check(&self.name, arg)?;
+            }
+        }
+        return (self.func)(&args);
+    }
+}
+
+impl std::fmt::Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Native({}, {:?})", self.name, self.params)
+    }
+}
+
+// Natives are compared by identity, like user-defined functions.
+impl PartialEq for Native {
+    fn eq(&self, other: &Native) -> bool {
+        return std::ptr::eq(self, other);
+    }
+}
+
+type FloatFn = fn(f64) -> f64;
+type ComplexFn = fn(Complex) -> Complex;
+
+pub const CONSTANTS: [(&str, f64); 5] = [
+    ("pi", std::f64::consts::PI),
+    ("e", std::f64::consts::E),
+    ("tau", std::f64::consts::TAU),
+    ("inf", f64::INFINITY),
+    ("nan", f64::NAN),
+];
+
+// The built-in library every Env starts with. Globals shadow these names.
+pub fn builtins() -> Vec<Rc<Native>> {
+    use Type::*;
+    use crate::complex::Complex; // the number, not Type::Complex
+
+    let unary: [(&str, FloatFn, ComplexFn); 15] = [
+        ("sin", f64::sin, Complex::sin),
+        ("cos", f64::cos, Complex::cos),
+        ("tan", f64::tan, Complex::tan),
+        ("asin", f64::asin, Complex::asin),
+        ("acos", f64::acos, Complex::acos),
+        ("atan", f64::atan, Complex::atan),
+        ("sinh", f64::sinh, Complex::sinh),
+        ("cosh", f64::cosh, Complex::cosh),
+        ("tanh", f64::tanh, Complex::tanh),
+        ("sqrt", f64::sqrt, Complex::sqrt),
+        ("cbrt", f64::cbrt, Complex::cbrt),
+        ("exp", f64::exp, Complex::exp),
+        ("ln", f64::ln, Complex::ln),
+        ("log2", f64::log2, Complex::log2),
+        ("log10", f64::log10, Complex::log10),
+    ];
+    let mut natives: Vec<Native> = unary
+        .iter()
+        .map(|&(name, f, g)| Native::new(name, &[Type::Complex], move |args| Ok(complex_aware(&args[0], f, g))))
+        .collect();
+
+    natives.extend([
+        Native::new("atan2", &[Float, Float], |a| Ok(Value::Float(float(&a[0]).atan2(float(&a[1]))))),
+        Native::new("hypot", &[Float, Float], |a| Ok(Value::Float(float(&a[0]).hypot(float(&a[1]))))),
+        Native::variadic("log", &[Type::Complex], native_log),
+        Native::new("abs", &[Number], native_abs),
+        Native::new("arg", &[Number], |a| Ok(Value::Float(complex(&a[0]).arg()))),
+        Native::new("conj", &[Number], |a| Ok(if let Value::Complex(z) = a[0] { Value::from(z.conj()) } else { a[0].clone() })),
+        Native::new("re", &[Number], |a| Ok(if let Value::Complex(z) = a[0] { Value::Float(z.re) } else { a[0].clone() })),
+        Native::new("im", &[Number], |a| Ok(if let Value::Complex(z) = a[0] { Value::Float(z.im) } else { Value::Int(0) })),
+        Native::variadic("min", &[Number], |a| extremum(a, 7)),
+        Native::variadic("max", &[Number], |a| extremum(a, 9)),
+        Native::new("floor", &[Number], |a| rounding(&a[0], f64::floor, Rational::floor, Rounding::Floor)),
+        Native::new("ceil", &[Number], |a| rounding(&a[0], f64::ceil, Rational::ceil, Rounding::Ceiling)),
+        Native::new("round", &[Number], |a| rounding(&a[0], f64::round, Rational::round, Rounding::HalfUp)),
+        Native::new("trunc", &[Number], |a| rounding(&a[0], f64::trunc, Rational::trunc, Rounding::Down)),
+        Native::new("sign", &[Number], native_sign),
+        Native::new("gcd", &[Int, Int], |a| Ok(Value::from(int(&a[0]).gcd(&int(&a[1]))))),
+        Native::new("lcm", &[Int, Int], native_lcm),
+        Native::new("factorial", &[Int], native_factorial),
+        Native::new("isqrt", &[Int], native_isqrt),
+    ]);
+    return natives.into_iter().map(Rc::new).collect();
+}
+
+// Arguments have been type-checked, so these only see the expected variants.
+fn float(v: &Value) -> f64 {
+    return v.as_f64().unwrap_or(f64::NAN);
+}
+
+fn int(v: &Value) -> BigInt {
+    return v.to_bigint().unwrap_or_default();
+}
+
+fn complex(v: &Value) -> Complex {
+    return v.to_complex().unwrap_or(Complex::from(f64::NAN));
+}
+
+// Real arguments go through `f`, unless it has no real answer for a
+// finite argument, as for `sqrt(-1)`; then `g` finds the complex one.
+fn complex_aware(v: &Value, f: FloatFn, g: ComplexFn) -> Value {
+    if let Value::Complex(z) = v {
+        return Value::from(g(*z));
+    }
+    let x = float(v);
+    let r = f(x);
+    if r.is_nan() && x.is_finite() {
+        return Value::from(g(Complex::from(x)));
+    }
+    return Value::Float(r);
+}
+
+// Rounding results are integral, so they become integers when finite.
+fn integral(f: f64) -> Value {
+    if f.is_finite() && f >= i64::MIN as f64 && f < i64::MAX as f64 {
+        return Value::Int(f as i64);
+    }
+    match BigInt::from_f64(f) {
+        Some(i) => Value::from(i),
+        None => Value::Float(f),
+    }
+}
+
+// log(x) is the natural logarithm, log(x, b) the logarithm to base b.
+fn native_log(args: &[Value]) -> Result<Value, RuntimeError> {
+    match args {
+        [x] => Ok(complex_aware(x, f64::ln, Complex::ln)),
+        [Value::Float(x), Value::Float(b)] if x.log(*b).is_nan() == (x.is_nan() || b.is_nan()) => {
+            Ok(Value::Float(x.log(*b)))
+        },
+        [x, b] => Ok(Value::from(complex(x).ln() / complex(b).ln())),
+        _ => Err(RuntimeError::ArityMismatch { name: "log".to_string(), expected: 2, found: args.len() }),
+    }
+}
+
+fn native_abs(args: &[Value]) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Int(_) | Value::BigInt(_) => Ok(Value::from(int(&args[0]).abs())),
+        Value::Rational(r) => Ok(Value::from(r.abs())),
+        Value::Decimal(d) => Ok(Value::Decimal(Rc::new(d.abs()))),
+        Value::Complex(z) => Ok(Value::Float(z.abs())),
+        v => Ok(Value::Float(float(v).abs())),
+    }
+}
+
+// `op` is the comparison a candidate must win against the current best.
+fn extremum(args: &[Value], op: i32) -> Result<Value, RuntimeError> {
+    let mut best = &args[0];
+    for v in &args[1..] {
+        if VM::compare(op, v, best)? == Value::Bool(true) {
+            best = v;
+        }
+    }
+    return Ok(best.clone());
+}
+
+// Fractions round exactly with `exact` and decimals with `mode`, everything
+// else goes through `f`.
+fn rounding(v: &Value, f: FloatFn, exact: fn(&Rational) -> BigInt, mode: Rounding) -> Result<Value, RuntimeError> {
+    match v {
+        Value::Int(_) | Value::BigInt(_) => Ok(v.clone()),
+        Value::Rational(r) => Ok(Value::from(exact(r))),
+        Value::Decimal(d) => Ok(Value::from(d.to_integer(mode))),
+        Value::Complex(z) => Ok(Value::from(Complex::new(f(z.re), f(z.im)))),
+        v => Ok(integral(f(float(v)))),
+    }
+}
+
+fn native_sign(args: &[Value]) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Int(i) => Ok(Value::Int(i.signum())),
+        Value::BigInt(i) => Ok(Value::Int(if i.is_negative() { -1 } else { 1 })),
+        Value::Rational(r) => Ok(Value::Int(if r.numer().is_negative() { -1 } else { 1 })),
+        Value::Decimal(d) if d.is_zero() => Ok(Value::Int(0)),
+        Value::Decimal(d) => Ok(Value::Int(if d.is_negative() { -1 } else { 1 })),
+        Value::Complex(z) => Ok(Value::from(*z / Complex::from(z.abs()))),
+        v => {
+            let f = float(v);
+            Ok(Value::Float(if f == 0.0 || f.is_nan() { f } else { f.signum() }))
+        }
+    }
+}
+
+fn native_lcm(args: &[Value]) -> Result<Value, RuntimeError> {
+    let (a, b) = (int(&args[0]), int(&args[1]));
+    if a.is_zero() || b.is_zero() {
+        return Ok(Value::Int(0));
+    }
+    return Ok(Value::from((&a.div_rem(&a.gcd(&b)).0 * &b).abs()));
+}
+
+fn native_factorial(args: &[Value]) -> Result<Value, RuntimeError> {
+    let n = int(&args[0]);
+    if n.is_negative() {
+        return Err(RuntimeError::InvalidArgument("factorial of a negative number".to_string()));
+    }
+    let n = n.to_i64().ok_or(RuntimeError::Overflow)?;
+    // log2(n!) is about n * log2(n / e); refuse early rather than grind
+    // through a product that ends up too large anyway.
+    let n_f = n as f64;
+    if n > 2 && n_f * (n_f / std::f64::consts::E).log2() > MAX_BITS as f64 {
+        return Err(RuntimeError::Overflow);
+    }
+    let mut r: i64 = 1;
+    let mut i = 2;
+    while i <= n {
+        match r.checked_mul(i) {
+            Some(p) => r = p,
+            None => break,
+        }
+        i += 1;
+    }
+    if i > n {
+        return Ok(Value::Int(r));
+    }
+    let mut big = BigInt::from(r);
+    for i in i..=n {
+        big = &big * &BigInt::from(i);
+    }
+    return Ok(Value::from(big));
+}
+
+fn native_isqrt(args: &[Value]) -> Result<Value, RuntimeError> {
+    let n = int(&args[0]);
+    if n.is_negative() {
+        return Err(RuntimeError::InvalidArgument("isqrt of a negative number".to_string()));
+    }
+    return Ok(Value::from(n.isqrt()));
+}
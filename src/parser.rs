@@ -0,0 +1,1106 @@
+use crate::bigint::BigInt;
+use crate::decimal::Decimal;
+use crate::lexer::{to_string, Diagnostic, Span, Token, TokenKind};
+use crate::units::Unit;
+use crate::vm::is_history_ref;
+
+/// A binary operator. `Mod` and `FloorDiv` round towards negative infinity;
+/// `In` converts a quantity to the unit on its right.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Mod,
+    FloorDiv,
+
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    In,
+}
+
+impl BinOp {
+    fn from_token(t: &TokenKind) -> Option<BinOp> {
+        match t {
+            TokenKind::Add => Some(BinOp::Add),
+            TokenKind::Sub => Some(BinOp::Sub),
+            TokenKind::Mul => Some(BinOp::Mul),
+            TokenKind::Div => Some(BinOp::Div),
+            TokenKind::Pow => Some(BinOp::Pow),
+            TokenKind::Percent => Some(BinOp::Mod),
+            TokenKind::SlashSlash => Some(BinOp::FloorDiv),
+
+            TokenKind::Amp => Some(BinOp::BitAnd),
+            TokenKind::Pipe => Some(BinOp::BitOr),
+            TokenKind::Xor => Some(BinOp::BitXor),
+            TokenKind::Shl => Some(BinOp::Shl),
+            TokenKind::Shr => Some(BinOp::Shr),
+
+            TokenKind::Eq => Some(BinOp::Eq),
+            TokenKind::Ne => Some(BinOp::Ne),
+            TokenKind::Lt => Some(BinOp::Lt),
+            TokenKind::Le => Some(BinOp::Le),
+            TokenKind::Gt => Some(BinOp::Gt),
+            TokenKind::Ge => Some(BinOp::Ge),
+            _ => None,
+        }
+    }
+}
+
+/// A prefix operator: `+`, `-`, `!` or `~`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnaryOp {
+    Plus,
+    Neg,
+    Not,
+    BitNot,
+}
+
+/// `&&` and `||`; they only evaluate their right operand when they have to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LogicOp {
+    And,
+    Or,
+}
+
+/// A node of the syntax tree and the source it was parsed from.
+#[derive(Clone, Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+/// What an expression is. Everything is an expression, including loops and
+/// function definitions; those are worth `()`.
+#[derive(Clone, Debug)]
+pub enum ExprKind {
+    Int(i64),
+    BigInt(BigInt),
+    Decimal(Decimal),
+    Float(f64),
+    Imag(f64),
+    /// One of a unit: the `m` of `3 m`, or the target of `in`.
+    Quantity(Unit),
+    Bool(bool),
+    Var(String),
+    Assign {
+        name: String,
+        value: Box<Expr>,
+    },
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Logical {
+        op: LogicOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        /// Without an `else` the `if` is Unit.
+        otherwise: Option<Box<Expr>>,
+    },
+    /// `{ a; b; c }` is worth `c`, `{ a; b; }` is Unit.
+    Block {
+        body: Vec<Expr>,
+        value: bool,
+    },
+    /// A whole input: statements separated by `;` or line breaks, worth
+    /// its last statement like a block. Empty input is Unit.
+    Program {
+        body: Vec<Expr>,
+        value: bool,
+    },
+    While {
+        cond: Box<Expr>,
+        body: Box<Expr>,
+    },
+    For {
+        var: String,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+        body: Box<Expr>,
+    },
+    Break,
+    Continue,
+    /// Binds a global function; the definition itself is Unit.
+    FnDef {
+        name: String,
+        params: Vec<String>,
+        body: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+}
+
+impl Expr {
+    fn new(kind: ExprKind, span: Span) -> Self {
+        Expr {
+            kind,
+            span,
+        }
+    }
+
+    fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Self {
+        let span = lhs.span.to(rhs.span);
+        Expr::new(
+            ExprKind::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            },
+            span,
+        )
+    }
+
+    // `start` is the span of a leading `if` keyword, if there is one.
+    fn conditional(cond: Expr, then: Expr, otherwise: Option<Expr>, start: Option<Span>) -> Self {
+        let end = otherwise.as_ref().map(|e| e.span).unwrap_or(then.span);
+        let span = start.unwrap_or(cond.span).to(end);
+        Expr::new(
+            ExprKind::If {
+                cond: Box::new(cond),
+                then: Box::new(then),
+                otherwise: otherwise.map(Box::new),
+            },
+            span,
+        )
+    }
+
+    // A chain such as `1 + 2 + 3` is as deep as it is long, so it is taken
+    // apart with a loop rather than recursion: the operators along the left
+    // edge, outermost first, and the operand at its end.
+    pub(crate) fn left_spine(&self) -> (&Expr, Vec<&Expr>) {
+        let mut spine = Vec::new();
+        let mut e = self;
+        while let ExprKind::Binary { lhs, .. } | ExprKind::Logical { lhs, .. } = &e.kind {
+            spine.push(e);
+            e = lhs;
+        }
+        return (e, spine);
+    }
+
+    // Names a function body assigns to, in order of first appearance. These
+    // become the function's local variables; nested definitions have their
+    // own scope and are skipped.
+    pub(crate) fn assigned_names(&self, out: &mut Vec<String>) {
+        match &self.kind {
+            ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Decimal(_) | ExprKind::Float(_) | ExprKind::Imag(_) => {},
+            ExprKind::Quantity(_) | ExprKind::Bool(_) | ExprKind::Var(_) => {},
+            ExprKind::Break | ExprKind::Continue | ExprKind::FnDef { .. } => {},
+            ExprKind::Assign { name, value } => {
+                value.assigned_names(out);
+                if !out.contains(name) {
+                    out.push(name.clone());
+                }
+            },
+            ExprKind::For { var, start, end, body, .. } => {
+                if !out.contains(var) {
+                    out.push(var.clone());
+                }
+                start.assigned_names(out);
+                end.assigned_names(out);
+                body.assigned_names(out);
+            },
+            ExprKind::Binary { .. } | ExprKind::Logical { .. } => {
+                let (leaf, spine) = self.left_spine();
+                leaf.assigned_names(out);
+                for e in spine.iter().rev() {
+                    if let ExprKind::Binary { rhs, .. } | ExprKind::Logical { rhs, .. } = &e.kind {
+                        rhs.assigned_names(out);
+                    }
+                }
+            },
+            ExprKind::Unary { operand, .. } => {
+                operand.assigned_names(out);
+            },
+            ExprKind::If { cond, then, otherwise } => {
+                cond.assigned_names(out);
+                then.assigned_names(out);
+                if let Some(e) = otherwise {
+                    e.assigned_names(out);
+                }
+            },
+            ExprKind::Block { body, .. } | ExprKind::Program { body, .. } => {
+                for e in body {
+                    e.assigned_names(out);
+                }
+            },
+            ExprKind::While { cond, body } => {
+                cond.assigned_names(out);
+                body.assigned_names(out);
+            },
+            ExprKind::Call { callee, args } => {
+                callee.assigned_names(out);
+                for e in args {
+                    e.assigned_names(out);
+                }
+            }
+        }
+    }
+
+    // Block-like expressions can be followed by another statement without
+    // a separating ';', e.g. `{ while c { .. } x }`.
+    fn ends_with_block(&self) -> bool {
+        match &self.kind {
+            ExprKind::Block { .. } | ExprKind::While { .. } | ExprKind::For { .. } => true,
+            ExprKind::FnDef { body, .. } => body.ends_with_block(),
+            ExprKind::If { then, otherwise, .. } => match otherwise {
+                Some(e) => e.ends_with_block(),
+                None => then.ends_with_block(),
+            },
+            _ => false,
+        }
+    }
+
+    // A number written out, possibly signed or raised to a power: the
+    // operands a unit gathers up in `term`.
+    fn is_literal(&self) -> bool {
+        match &self.kind {
+            ExprKind::Int(_) | ExprKind::BigInt(_) | ExprKind::Decimal(_) | ExprKind::Float(_) | ExprKind::Imag(_) => true,
+            ExprKind::Unary { op: UnaryOp::Plus | UnaryOp::Neg, operand } => operand.is_literal(),
+            ExprKind::Binary { op: BinOp::Pow, lhs, rhs } => lhs.is_literal() && rhs.is_literal(),
+            _ => false,
+        }
+    }
+
+    /// The tree on one line, e.g. `Binary(Add,Int(1),Var(x))`.
+    pub fn repr(&self) -> String {
+        match &self.kind {
+            ExprKind::Int(i) => format!("Int({})", i),
+            ExprKind::BigInt(i) => format!("BigInt({})", i),
+            ExprKind::Decimal(d) => format!("Decimal({})", d),
+            ExprKind::Float(f) => format!("Float({})", f),
+            ExprKind::Imag(f) => format!("Imag({})", f),
+            ExprKind::Quantity(u) => format!("Quantity({})", u),
+            ExprKind::Bool(b) => format!("Bool({})", b),
+            ExprKind::Var(name) => format!("Var({})", name),
+            ExprKind::Assign { name, value } => format!("Assign({},{})", name, value.repr()),
+            ExprKind::Binary { op, lhs, rhs } => {
+                format!("Binary({:?},{},{})", op, lhs.repr(), rhs.repr())
+            },
+            ExprKind::Unary { op, operand } => {
+                format!("Unary({:?},{})", op, operand.repr())
+            },
+            ExprKind::Logical { op, lhs, rhs } => {
+                format!("Logical({:?},{},{})", op, lhs.repr(), rhs.repr())
+            },
+            ExprKind::If { cond, then, otherwise } => match otherwise {
+                Some(e) => format!("If({},{},{})", cond.repr(), then.repr(), e.repr()),
+                None => format!("If({},{})", cond.repr(), then.repr()),
+            },
+            ExprKind::Block { body, value } => {
+                let body = body.iter().map(|e| e.repr()).collect::<Vec<_>>().join(";");
+                format!("Block({}{})", body, if *value { "" } else { ";" })
+            },
+            ExprKind::Program { body, value } => {
+                let body = body.iter().map(|e| e.repr()).collect::<Vec<_>>().join(";");
+                format!("Program({}{})", body, if *value { "" } else { ";" })
+            },
+            ExprKind::While { cond, body } => format!("While({},{})", cond.repr(), body.repr()),
+            ExprKind::For { var, start, end, inclusive, body } => {
+                let range = if *inclusive { "..=" } else { ".." };
+                format!("For({},{}{}{},{})", var, start.repr(), range, end.repr(), body.repr())
+            },
+            ExprKind::Break => "Break".to_string(),
+            ExprKind::Continue => "Continue".to_string(),
+            ExprKind::FnDef { name, params, body } => {
+                format!("FnDef({}({}),{})", name, params.join(","), body.repr())
+            },
+            ExprKind::Call { callee, args } => {
+                let args = args.iter().map(|e| e.repr()).collect::<Vec<_>>().join(",");
+                format!("Call({},{})", callee.repr(), args)
+            },
+        }
+    }
+}
+
+// Operands nested inside one another, through parentheses, blocks,
+// prefix operators, `^` or the arms of `?:`. Each level is a dozen
+// recursive calls through the precedence levels, around 8 KB of stack in
+// a release build and up to 40 KB in a debug build, so without a limit
+// deep input overflows the stack instead of failing with a diagnostic.
+// At this limit parsing and compiling stay within 5 MB even in a debug
+// build, which a main thread's usual 8 MB stack holds.
+pub const MAX_NESTING: usize = 128;
+
+// Dropping recursively would overflow the stack on the same long chains,
+// so children are moved out and dropped from a work list instead.
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut work = Vec::new();
+        let mut kind = std::mem::replace(&mut self.kind, ExprKind::Break);
+        loop {
+            match kind {
+                ExprKind::Assign { value, .. } => work.push(*value),
+                ExprKind::Binary { lhs, rhs, .. } | ExprKind::Logical { lhs, rhs, .. } => {
+                    work.push(*lhs);
+                    work.push(*rhs);
+                },
+                ExprKind::Unary { operand, .. } => work.push(*operand),
+                ExprKind::If { cond, then, otherwise } => {
+                    work.push(*cond);
+                    work.push(*then);
+                    work.extend(otherwise.map(|e| *e));
+                },
+                ExprKind::Block { body, .. } | ExprKind::Program { body, .. } => work.extend(body),
+                ExprKind::While { cond, body } => {
+                    work.push(*cond);
+                    work.push(*body);
+                },
+                ExprKind::For { start, end, body, .. } => {
+                    work.push(*start);
+                    work.push(*end);
+                    work.push(*body);
+                },
+                ExprKind::FnDef { body, .. } => work.push(*body),
+                ExprKind::Call { callee, args } => {
+                    work.push(*callee);
+                    work.extend(args);
+                },
+                _ => {}
+            }
+            match work.pop() {
+                Some(mut e) => kind = std::mem::replace(&mut e.kind, ExprKind::Break),
+                None => break,
+            }
+        }
+    }
+}
+
+pub struct Perser {
+    tokens: Vec<Token>,
+    position: usize,
+    loops: usize, // how many loops enclose the current position
+    nesting: usize, // how many operands enclose the current position
+}
+
+impl Perser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Perser {
+            tokens,
+            position: 0,
+            loops: 0,
+            nesting: 0,
+        }
+    }
+
+    pub fn parser(&mut self) -> Result<Expr, Diagnostic> {
+        // statements EOF
+
+        let start = self.span();
+        let (body, value) = self.statements(&TokenKind::EOF, "';', a new line or end of input")?;
+        let span = body.last().map_or(start, |e| start.to(e.span));
+        return Ok(Expr::new(ExprKind::Program { body, value }, span));
+    }
+
+    // [statement ((';' | NEWLINE | <after a block>) statement)* [';']] up to
+    // `end`, which is left for the caller. The sequence is worth its last
+    // statement unless a `;` follows it.
+    fn statements(&mut self, end: &TokenKind, expected: &str) -> Result<(Vec<Expr>, bool), Diagnostic> {
+        let mut body = Vec::new();
+        let mut value = false;
+
+        loop {
+            self.skip_newlines();
+            if self.kind() == end {
+                break;
+            }
+            let stmt = self.statement()?;
+            let block_like = stmt.ends_with_block();
+            body.push(stmt);
+            value = true;
+
+            match self.kind() {
+                TokenKind::Semicolon => {
+                    self.position += 1;
+                    value = false;
+                },
+                TokenKind::Newline => {},
+                k if k == end || block_like => {},
+                _ => {
+                    return Err(self.unexpected(expected));
+                }
+            }
+        }
+
+        return Ok((body, value));
+    }
+
+    fn skip_newlines(&mut self) {
+        while matches!(self.kind(), TokenKind::Newline) {
+            self.position += 1;
+        }
+    }
+
+    // Lets `kind` continue an expression on the next line, as in
+    // `if c then a` followed by `else b` on a line of its own.
+    fn continues_with(&mut self, kind: TokenKind) -> bool {
+        let mut n = 0;
+        while matches!(self.peek_kind(n), TokenKind::Newline) {
+            n += 1;
+        }
+        if self.peek_kind(n) != &kind {
+            return false;
+        }
+        self.position += n;
+        return true;
+    }
+
+    fn kind(&self) -> &TokenKind {
+        return &self.tokens.get(self.position).unwrap().kind;
+    }
+
+    fn peek_kind(&self, n: usize) -> &TokenKind {
+        let i = (self.position + n).min(self.tokens.len() - 1);
+        return &self.tokens[i].kind;
+    }
+
+    fn span(&self) -> Span {
+        return self.tokens.get(self.position).unwrap().span;
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &str) -> Result<Span, Diagnostic> {
+        if self.kind() != &kind {
+            return Err(self.unexpected(expected));
+        }
+        let span = self.span();
+        self.position += 1;
+        return Ok(span);
+    }
+
+    fn unexpected(&self, expected: &str) -> Diagnostic {
+        let t = self.kind();
+        if matches!(t, TokenKind::EOF) {
+            return Diagnostic::new(format!("Expected {}, found end of input", expected), self.span());
+        }
+        if matches!(t, TokenKind::Newline) {
+            return Diagnostic::new(format!("Expected {}, found end of line", expected), self.span());
+        }
+        return Diagnostic::new(format!("Expected {}, found '{}'", expected, to_string(t)), self.span());
+    }
+
+    // Parses `next (op next)*` left-associatively for the operators in `ops`.
+    fn left_assoc(
+        &mut self,
+        ops: &[BinOp],
+        next: fn(&mut Perser) -> Result<Expr, Diagnostic>,
+    ) -> Result<Expr, Diagnostic> {
+        let mut left = next(self)?;
+
+        loop {
+            let op = match BinOp::from_token(self.kind()) {
+                Some(op) if ops.contains(&op) => op,
+                _ => break,
+            };
+            self.position += 1;
+            let right = next(self)?;
+            left = Expr::binary(op, left, right);
+        }
+
+        return Ok(left);
+    }
+
+    fn statement(&mut self) -> Result<Expr, Diagnostic> {
+        // IDENT '=' expr
+        // expr
+
+        if let TokenKind::Ident(name) = self.kind()
+            && matches!(self.peek_kind(1), TokenKind::Assign)
+        {
+            let name = name.clone();
+            let start = self.span();
+            if is_history_ref(&name) {
+                return Err(Diagnostic::new(
+                    format!("Cannot assign to '{}', it refers to a previous result", name),
+                    start,
+                ));
+            }
+            self.position += 2;
+
+            let value = self.expr()?;
+            let span = start.to(value.span);
+            return Ok(Expr::new(
+                ExprKind::Assign {
+                    name,
+                    value: Box::new(value),
+                },
+                span,
+            ));
+        }
+
+        return self.expr();
+    }
+
+    fn expr(&mut self) -> Result<Expr, Diagnostic> {
+        // convert ['?' expr ':' expr]
+
+        let cond = self.convert()?;
+        if !self.continues_with(TokenKind::Question) {
+            return Ok(cond);
+        }
+        self.position += 1;
+        let then = self.nested(Perser::expr)?;
+        self.continues_with(TokenKind::Colon);
+        self.expect(TokenKind::Colon, "':'")?;
+        let otherwise = self.nested(Perser::expr)?;
+        return Ok(Expr::conditional(cond, then, Some(otherwise), None));
+    }
+
+    fn convert(&mut self) -> Result<Expr, Diagnostic> {
+        // or (('in' | 'to') unit)*
+
+        let mut left = self.or()?;
+        while matches!(self.kind(), TokenKind::In) || matches!(self.kind(), TokenKind::Ident(n) if n == "to") {
+            self.position += 1;
+            let unit = self.unit()?;
+            left = Expr::binary(BinOp::In, left, unit);
+        }
+        return Ok(left);
+    }
+
+    fn unit(&mut self) -> Result<Expr, Diagnostic> {
+        // unit_power (('*'|'/') unit_power)*
+
+        let start = self.span();
+        let mut unit = self.unit_power()?;
+        loop {
+            let div = match self.kind() {
+                TokenKind::Mul => false,
+                TokenKind::Div => true,
+                _ => break,
+            };
+            self.position += 1;
+            let factor = self.unit_power()?;
+            let product = if div { unit.div(&factor) } else { unit.mul(&factor) };
+            unit = product.ok_or_else(|| Diagnostic::new("Unit power is too large", start.to(self.tokens[self.position - 1].span)))?;
+        }
+        let span = start.to(self.tokens[self.position - 1].span);
+        return Ok(Expr::new(ExprKind::Quantity(unit), span));
+    }
+
+    fn unit_power(&mut self) -> Result<Unit, Diagnostic> {
+        // IDENT ['^' ['-'] INT]
+
+        let unit = match self.kind() {
+            TokenKind::Ident(name) => match Unit::lookup(name) {
+                Some(u) => u,
+                None => {
+                    return Err(Diagnostic::new(format!("Unknown unit '{}'", name), self.span()));
+                }
+            },
+            _ => {
+                return Err(self.unexpected("a unit"));
+            }
+        };
+        self.position += 1;
+        if !matches!(self.kind(), TokenKind::Pow) {
+            return Ok(unit);
+        }
+        self.position += 1;
+        let neg = matches!(self.kind(), TokenKind::Sub);
+        if neg {
+            self.position += 1;
+        }
+        match *self.kind() {
+            TokenKind::Int(n) => {
+                let span = self.span();
+                self.position += 1;
+                let unit = i32::try_from(if neg { -n } else { n }).ok().and_then(|n| unit.powi(n));
+                return unit.ok_or_else(|| Diagnostic::new("Unit power is too large", span));
+            },
+            _ => {
+                return Err(self.unexpected("a whole power"));
+            }
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, Diagnostic> {
+        // and ('||' and)*
+        return self.logical(TokenKind::OrOr, LogicOp::Or, Perser::and);
+    }
+
+    fn if_expr(&mut self) -> Result<Expr, Diagnostic> {
+        // 'if' expr 'then' expr 'else' expr
+        // 'if' expr block ['else' expr]
+
+        let start = self.span();
+        self.position += 1;
+        let cond = self.expr()?;
+        let block = self.continues_with(TokenKind::LBrace);
+        let then = if block {
+            self.block()?
+        } else {
+            self.continues_with(TokenKind::Then);
+            self.expect(TokenKind::Then, "'then' or '{'")?;
+            self.expr()?
+        };
+        if !self.continues_with(TokenKind::Else) && block {
+            return Ok(Expr::conditional(cond, then, None, Some(start)));
+        }
+        self.expect(TokenKind::Else, "'else'")?;
+        let otherwise = self.expr()?;
+        return Ok(Expr::conditional(cond, then, Some(otherwise), Some(start)));
+    }
+
+    fn block(&mut self) -> Result<Expr, Diagnostic> {
+        // '{' statements '}'
+
+        self.continues_with(TokenKind::LBrace);
+        let start = self.expect(TokenKind::LBrace, "'{'")?;
+        let (body, value) = self.statements(&TokenKind::RBrace, "';', a new line or '}'")?;
+        let end = self.span();
+        self.position += 1;
+
+        return Ok(Expr::new(ExprKind::Block { body, value }, start.to(end)));
+    }
+
+    // Parses a loop body with `break`/`continue` allowed inside it.
+    fn loop_body(&mut self) -> Result<Expr, Diagnostic> {
+        self.loops += 1;
+        let body = self.block();
+        self.loops -= 1;
+        return body;
+    }
+
+    fn while_expr(&mut self) -> Result<Expr, Diagnostic> {
+        // 'while' expr block
+
+        let start = self.span();
+        self.position += 1;
+        let cond = self.expr()?;
+        let body = self.loop_body()?;
+        let span = start.to(body.span);
+        return Ok(Expr::new(
+            ExprKind::While {
+                cond: Box::new(cond),
+                body: Box::new(body),
+            },
+            span,
+        ));
+    }
+
+    fn for_expr(&mut self) -> Result<Expr, Diagnostic> {
+        // 'for' IDENT 'in' expr ('..'|'..=') expr block
+
+        let start = self.span();
+        self.position += 1;
+        let var = self.name("loop variable")?;
+        self.expect(TokenKind::In, "'in'")?;
+
+        let from = self.expr()?;
+        let inclusive = match self.kind() {
+            TokenKind::DotDot => false,
+            TokenKind::DotDotEq => true,
+            _ => {
+                return Err(self.unexpected("'..' or '..='"));
+            }
+        };
+        self.position += 1;
+        let to = self.expr()?;
+        let body = self.loop_body()?;
+        let span = start.to(body.span);
+        return Ok(Expr::new(
+            ExprKind::For {
+                var,
+                start: Box::new(from),
+                end: Box::new(to),
+                inclusive,
+                body: Box::new(body),
+            },
+            span,
+        ));
+    }
+
+    fn and(&mut self) -> Result<Expr, Diagnostic> {
+        // comparison ('&&' comparison)*
+        return self.logical(TokenKind::AndAnd, LogicOp::And, Perser::comparison);
+    }
+
+    fn logical(
+        &mut self,
+        token: TokenKind,
+        op: LogicOp,
+        next: fn(&mut Perser) -> Result<Expr, Diagnostic>,
+    ) -> Result<Expr, Diagnostic> {
+        let mut left = next(self)?;
+
+        while self.kind() == &token {
+            self.position += 1;
+            let right = next(self)?;
+            let span = left.span.to(right.span);
+            left = Expr::new(
+                ExprKind::Logical {
+                    op,
+                    lhs: Box::new(left),
+                    rhs: Box::new(right),
+                },
+                span,
+            );
+        }
+
+        return Ok(left);
+    }
+
+    fn comparison(&mut self) -> Result<Expr, Diagnostic> {
+        // bit_or [('=='|'!='|'<'|'<='|'>'|'>=') bit_or]
+
+        const CMP: [BinOp; 6] = [BinOp::Eq, BinOp::Ne, BinOp::Lt, BinOp::Le, BinOp::Gt, BinOp::Ge];
+        let left = self.bit_or()?;
+        let op = match BinOp::from_token(self.kind()) {
+            Some(op) if CMP.contains(&op) => op,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.bit_or()?;
+
+        // `a < b < c` would compare a Bool with c; make the user spell it out.
+        if let Some(op) = BinOp::from_token(self.kind())
+            && CMP.contains(&op)
+        {
+            return Err(Diagnostic::new(
+                "Comparison operators cannot be chained, use '&&' to combine them",
+                self.span(),
+            ));
+        }
+        return Ok(Expr::binary(op, left, right));
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, Diagnostic> {
+        // bit_xor ('|' bit_xor)*
+        return self.left_assoc(&[BinOp::BitOr], Perser::bit_xor);
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, Diagnostic> {
+        // bit_and ('xor' bit_and)*
+        return self.left_assoc(&[BinOp::BitXor], Perser::bit_and);
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, Diagnostic> {
+        // shift ('&' shift)*
+        return self.left_assoc(&[BinOp::BitAnd], Perser::shift);
+    }
+
+    fn shift(&mut self) -> Result<Expr, Diagnostic> {
+        // sum (('<<'|'>>') sum)*
+        return self.left_assoc(&[BinOp::Shl, BinOp::Shr], Perser::sum);
+    }
+
+    fn sum(&mut self) -> Result<Expr, Diagnostic> {
+        // term (('+'|'-') term)*
+        return self.left_assoc(&[BinOp::Add, BinOp::Sub], Perser::term);
+    }
+
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
+        // factor [UNIT] (('*'|'/'|'//'|'%') factor [UNIT])*
+        //
+        // The lexer only puts a unit right after a number. It multiplies
+        // the literal factors back to the previous unit, so `2^3 m` is 8
+        // metres, `1/2.0 m` half a metre and `10 m / 2 s` is 5 m/s. Any
+        // other operand ends the run: `x / 2 s` divides x by 2 seconds.
+        // Without units this is an ordinary left-associative chain.
+        let ops = [BinOp::Mul, BinOp::Div, BinOp::FloorDiv, BinOp::Mod];
+        let mut done: Option<Expr> = None; // everything up to the unit's run
+        let mut factors = vec![(None, self.factor()?)]; // since, with their operators
+
+        loop {
+            if let TokenKind::Unit(u) = self.kind() {
+                let unit = Expr::new(ExprKind::Quantity(u.clone()), self.span());
+                self.position += 1;
+                // `m ^ 2` would otherwise square the whole quantity
+                if matches!(self.kind(), TokenKind::Pow) {
+                    return Err(Diagnostic::new("A unit power is written without spaces, as in 'm^2'", self.span()));
+                }
+                let run = factors.iter().rposition(|(_, f)| !f.is_literal()).map_or(0, |i| i + 1);
+                let mut run = factors.split_off(run.min(factors.len() - 1)).into_iter();
+                for (op, f) in factors.drain(..) {
+                    done = Some(match (done, op) {
+                        (Some(d), Some(op)) => Expr::binary(op, d, f),
+                        _ => f,
+                    });
+                }
+                let (op, first) = run.next().unwrap();
+                let number = run.fold(first, |l, (op, r)| Expr::binary(op.unwrap(), l, r));
+                let q = Expr::binary(BinOp::Mul, number, unit);
+                done = Some(match (done, op) {
+                    (Some(d), Some(op)) => Expr::binary(op, d, q),
+                    _ => q,
+                });
+            }
+            let op = match BinOp::from_token(self.kind()) {
+                Some(op) if ops.contains(&op) => op,
+                _ => break,
+            };
+            self.position += 1;
+            factors.push((Some(op), self.factor()?));
+        }
+
+        let mut rest = factors.into_iter();
+        let mut left = match done {
+            Some(d) => d,
+            None => rest.next().unwrap().1,
+        };
+        for (op, right) in rest {
+            left = Expr::binary(op.unwrap(), left, right);
+        }
+        return Ok(left);
+    }
+
+    fn factor(&mut self) -> Result<Expr, Diagnostic> {
+        // Every operand starts here, which makes it the place to count
+        // how deeply they nest.
+        return self.nested(Perser::operand);
+    }
+
+    // `parse` one level deeper, or fail once that is past MAX_NESTING.
+    // Whatever recurses into itself other than through `factor`, such as
+    // the arms of `?:`, goes through here as well.
+    fn nested(&mut self, parse: fn(&mut Perser) -> Result<Expr, Diagnostic>) -> Result<Expr, Diagnostic> {
+        if self.nesting == MAX_NESTING {
+            return Err(Diagnostic::new(
+                format!("Expression nested too deeply, the limit is {} levels", MAX_NESTING),
+                self.span(),
+            ));
+        }
+        self.nesting += 1;
+        let e = parse(self);
+        self.nesting -= 1;
+        return e;
+    }
+
+    fn operand(&mut self) -> Result<Expr, Diagnostic> {
+        // ('+'|'-'|'!'|'~') factor
+        // power
+
+        // Every operand starts here, so `1 +` may continue on the next line
+        self.skip_newlines();
+        let op = match self.kind() {
+            TokenKind::Add => UnaryOp::Plus,
+            TokenKind::Sub => UnaryOp::Neg,
+            TokenKind::Not => UnaryOp::Not,
+            TokenKind::Tilde => UnaryOp::BitNot,
+            _ => return self.power(),
+        };
+        let start = self.span();
+        self.position += 1;
+
+        let operand = self.factor()?;
+        let span = start.to(operand.span);
+        return Ok(Expr::new(
+            ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            },
+            span,
+        ));
+    }
+
+    fn power(&mut self) -> Result<Expr, Diagnostic> {
+        // call ['^' factor]
+
+        let left = self.call()?;
+        if !matches!(self.kind(), TokenKind::Pow) {
+            return Ok(left);
+        }
+        self.position += 1;
+
+        // The exponent may carry its own sign: 2^-1
+        let right = self.factor()?;
+        return Ok(Expr::binary(BinOp::Pow, left, right));
+    }
+
+    fn call(&mut self) -> Result<Expr, Diagnostic> {
+        // atom ('(' [expr (',' expr)*] ')')*
+
+        let mut callee = self.atom()?;
+        while matches!(self.kind(), TokenKind::LParen) {
+            self.position += 1;
+            let mut args = Vec::new();
+            while !matches!(self.kind(), TokenKind::RParen) {
+                args.push(self.expr()?);
+                if !matches!(self.kind(), TokenKind::RParen) {
+                    self.expect(TokenKind::Comma, "',' or ')'")?;
+                }
+            }
+            let span = callee.span.to(self.span());
+            self.position += 1;
+            callee = Expr::new(
+                ExprKind::Call {
+                    callee: Box::new(callee),
+                    args,
+                },
+                span,
+            );
+        }
+
+        return Ok(callee);
+    }
+
+    fn name(&mut self, what: &str) -> Result<String, Diagnostic> {
+        let name = match self.kind() {
+            TokenKind::Ident(name) => name.clone(),
+            _ => {
+                return Err(self.unexpected(what));
+            }
+        };
+        if is_history_ref(&name) {
+            return Err(Diagnostic::new(
+                format!("Cannot assign to '{}', it refers to a previous result", name),
+                self.span(),
+            ));
+        }
+        self.position += 1;
+        return Ok(name);
+    }
+
+    fn fn_def(&mut self) -> Result<Expr, Diagnostic> {
+        // 'fn' IDENT '(' [IDENT (',' IDENT)*] ')' ('=' expr | block)
+
+        let start = self.span();
+        self.position += 1;
+        let name = self.name("function name")?;
+
+        self.expect(TokenKind::LParen, "'('")?;
+        let mut params: Vec<String> = Vec::new();
+        while !matches!(self.kind(), TokenKind::RParen) {
+            let at = self.span();
+            let param = self.name("parameter name")?;
+            if params.contains(&param) {
+                return Err(Diagnostic::new(format!("Duplicate parameter '{}'", param), at));
+            }
+            params.push(param);
+            if !matches!(self.kind(), TokenKind::RParen) {
+                self.expect(TokenKind::Comma, "',' or ')'")?;
+            }
+        }
+        self.position += 1;
+
+        // A loop around the definition does not extend into the body.
+        let loops = std::mem::take(&mut self.loops);
+        let body = if matches!(self.kind(), TokenKind::LBrace) {
+            self.block()
+        } else {
+            self.expect(TokenKind::Assign, "'=' or '{'").and_then(|_| self.expr())
+        };
+        self.loops = loops;
+        let body = body?;
+
+        let span = start.to(body.span);
+        return Ok(Expr::new(
+            ExprKind::FnDef {
+                name,
+                params,
+                body: Box::new(body),
+            },
+            span,
+        ));
+    }
+
+    fn atom(&mut self) -> Result<Expr, Diagnostic> {
+        // (INT | FLOAT | 'true' | 'false' | IDENT)
+        // '(' expr ')'
+        // if_expr | while_expr | for_expr | block | fn_def
+        // 'break' | 'continue'
+
+        let span = self.span();
+        match *self.kind() {
+            TokenKind::Fn => {
+                return self.fn_def();
+            },
+            TokenKind::If => {
+                return self.if_expr();
+            },
+            TokenKind::While => {
+                return self.while_expr();
+            },
+            TokenKind::For => {
+                return self.for_expr();
+            },
+            TokenKind::LBrace => {
+                return self.block();
+            },
+            TokenKind::Break | TokenKind::Continue => {
+                let kind = match self.kind() {
+                    TokenKind::Break => ExprKind::Break,
+                    _ => ExprKind::Continue,
+                };
+                if self.loops == 0 {
+                    return Err(Diagnostic::new(
+                        format!("'{}' outside of a loop", to_string(self.kind())),
+                        span,
+                    ));
+                }
+                self.position += 1;
+                return Ok(Expr::new(kind, span));
+            },
+            TokenKind::True | TokenKind::False => {
+                let b = matches!(self.kind(), TokenKind::True);
+                self.position += 1;
+                return Ok(Expr::new(ExprKind::Bool(b), span));
+            },
+            TokenKind::Ident(ref name) => {
+                let name = name.clone();
+                self.position += 1;
+                return Ok(Expr::new(ExprKind::Var(name), span));
+            },
+            TokenKind::Int(i) => {
+                self.position += 1;
+                return Ok(Expr::new(ExprKind::Int(i), span));
+            },
+            TokenKind::BigInt(ref i) => {
+                let i = i.clone();
+                self.position += 1;
+                return Ok(Expr::new(ExprKind::BigInt(i), span));
+            },
+            TokenKind::Decimal(ref d) => {
+                let d = d.clone();
+                self.position += 1;
+                return Ok(Expr::new(ExprKind::Decimal(d), span));
+            },
+            TokenKind::Float(f) => {
+                self.position += 1;
+                return Ok(Expr::new(ExprKind::Float(f), span));
+            },
+            TokenKind::Imag(f) => {
+                self.position += 1;
+                return Ok(Expr::new(ExprKind::Imag(f), span));
+            },
+            TokenKind::LParen => {
+                self.position += 1;
+                let mut node = self.expr()?;
+                if !matches!(self.kind(), TokenKind::RParen) {
+                    return Err(self.unexpected("')'"));
+                }
+                node.span = span.to(self.span());
+                self.position += 1;
+                return Ok(node);
+            },
+            _ => {
+                return Err(self.unexpected("atom"));
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::cmp::Ordering;
+
+use crate::bigint::BigInt;
+
+/// An exact fraction, always in lowest terms with a positive denominator.
+///
+/// Only exact mode creates these: dividing two integers there gives a
+/// `Rational` instead of truncating.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rational {
+    num: BigInt,
+    den: BigInt,
+}
+
+impl Rational {
+    /// `num / den` in lowest terms, or None when `den` is zero.
+    pub fn new(num: BigInt, den: BigInt) -> Option<Rational> {
+        if den.is_zero() {
+            return None;
+        }
+        let g = num.gcd(&den);
+        let (mut num, mut den) = (num.div_rem(&g).0, den.div_rem(&g).0);
+        if den.is_negative() {
+            num = -&num;
+            den = -&den;
+        }
+        return Some(Rational { num, den });
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        return &self.num;
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        return &self.den;
+    }
+
+    pub fn is_integer(&self) -> bool {
+        return self.den == BigInt::from(1);
+    }
+
+    /// Size of the larger of numerator and denominator, in bits.
+    pub fn bits(&self) -> u64 {
+        return self.num.bits().max(self.den.bits());
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Drop low bits that f64 could not hold anyway, so that huge
+        // numerators and denominators do not both round to infinity.
+        let shift = self.bits().saturating_sub(1000) as usize;
+        return (&self.num >> shift).to_f64() / (&self.den >> shift).to_f64();
+    }
+
+    pub fn recip(&self) -> Option<Rational> {
+        return Rational::new(self.den.clone(), self.num.clone());
+    }
+
+    pub fn abs(&self) -> Rational {
+        return Rational { num: self.num.abs(), den: self.den.clone() };
+    }
+
+    pub fn floor(&self) -> BigInt {
+        return self.num.div_mod_floor(&self.den).0;
+    }
+
+    pub fn ceil(&self) -> BigInt {
+        return -&(-self).floor();
+    }
+
+    pub fn trunc(&self) -> BigInt {
+        return self.num.div_rem(&self.den).0;
+    }
+
+    /// Rounds half away from zero, like `f64::round`.
+    pub fn round(&self) -> BigInt {
+        let half = Rational { num: BigInt::from(1), den: BigInt::from(2) };
+        let r = (&self.abs() + &half).floor();
+        return if self.num.is_negative() { -&r } else { r };
+    }
+
+    /// `self` raised to an integer power; None for a negative power of zero
+    /// or a power beyond `u32::MAX`.
+    pub fn pow(&self, e: i64) -> Option<Rational> {
+        let n = u32::try_from(e.unsigned_abs()).ok()?;
+        let r = Rational { num: self.num.pow(n), den: self.den.pow(n) };
+        return if e < 0 { r.recip() } else { Some(r) };
+    }
+
+    /// `3 1/2` rather than `7/2`; proper fractions print as they are.
+    pub fn mixed(&self) -> String {
+        let whole = self.trunc();
+        if whole.is_zero() || self.is_integer() {
+            return self.to_string();
+        }
+        let rest = (&self.num - &(&whole * &self.den)).abs();
+        return format!("{} {}/{}", whole, rest, self.den);
+    }
+}
+
+impl From<BigInt> for Rational {
+    fn from(i: BigInt) -> Rational {
+        return Rational { num: i, den: BigInt::from(1) };
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        return (&self.num * &other.den).cmp(&(&other.num * &self.den));
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl std::fmt::Display for Rational {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_integer() {
+            return write!(f, "{}", self.num);
+        }
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+impl std::ops::Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        return Rational { num: -&self.num, den: self.den.clone() };
+    }
+}
+
+impl std::ops::Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        let num = &(&self.num * &other.den) + &(&other.num * &self.den);
+        return Rational::new(num, &self.den * &other.den).unwrap();
+    }
+}
+
+impl std::ops::Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        return self + &(-other);
+    }
+}
+
+impl std::ops::Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        return Rational::new(&self.num * &other.num, &self.den * &other.den).unwrap();
+    }
+}
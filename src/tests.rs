@@ -0,0 +1,817 @@
+use crate::bigint::BigInt;
+use crate::compiler::{ByteCode, ByteCodes, Dis};
+use crate::decimal::{Context, Decimal, Rounding, MAX_PRECISION};
+use crate::engine::Engine;
+use crate::lexer::{Diagnostic, Lexer, Span, TokenKind};
+use crate::natives::Type;
+use crate::parser::{Expr, ExprKind, Perser};
+use crate::rational::Rational;
+use crate::value::{RuntimeError, Value};
+use crate::vm::{Env, VM};
+
+// Operators cycle through every Int/Float combination so each promotion
+// path is exercised; the nesting keeps the operand stack deep.
+fn nested_expression(depth: usize) -> String {
+    let mut src = "1".to_string();
+    for i in 0..depth {
+        src = match i % 4 {
+            0 => format!("({} + {})", i, src),
+            1 => format!("({} * 1.0001 - {})", src, i),
+            2 => format!("({} - ({} / 3))", i, src),
+            _ => format!("(2.5 + {} * 1)", src),
+        };
+    }
+    return src;
+}
+
+#[test]
+fn lexer_spans_count_utf8_correctly() {
+    let tokens = Lexer::new("π = 3\n  ä+€".to_string()).next_token();
+    let e = match tokens {
+        Err(e) => e,
+        Ok(_) => panic!("'€' should not lex"),
+    };
+    assert_eq!(e.span, Span::new(12, 15, 2, 5));
+
+    let tokens = Lexer::new("ä + π".to_string()).next_token().ok().unwrap();
+    let spans: Vec<Span> = tokens.iter().map(|t| t.span).collect();
+    assert_eq!(spans[0], Span::new(0, 2, 1, 1));
+    assert_eq!(spans[1], Span::new(3, 4, 1, 3));
+    assert_eq!(spans[2], Span::new(5, 7, 1, 5));
+    assert_eq!(spans[3], Span::new(7, 7, 1, 6));
+}
+
+fn diagnose(src: &str) -> String {
+    let result = Lexer::new(src.to_string()).next_token().and_then(|tokens| Perser::new(tokens).parser());
+    return match result {
+        Ok(_) => panic!("{:?} should not parse", src),
+        Err(e) => e.render(src),
+    };
+}
+
+#[test]
+fn diagnostics_render_carets_under_the_span() {
+    // Only the offending line is shown, with a caret per character
+    assert_eq!(diagnose("1 +\n2 * (3 4)"), "error: Expected ')', found '4'\n --> 2:8\n  |\n2 | 2 * (3 4)\n  |        ^");
+    assert_eq!(diagnose("1 +\n0x"), "error: Expected digits after '0x'\n --> 2:1\n  |\n2 | 0x\n  | ^^");
+
+    // Columns count characters, not bytes, and tabs are kept in the padding
+    assert_eq!(diagnose("1 +\n\t2 * €"), "error: Unexpected character: '€'\n --> 2:6\n  |\n2 | \t2 * €\n  | \t    ^");
+    let d = Diagnostic::new("Expected a number", Span::new(4, 9, 1, 5));
+    assert_eq!(d.render("1 + πr² * ä"), "error: Expected a number\n --> 1:5\n  |\n1 | 1 + πr² * ä\n  |     ^^^");
+
+    // At the end of the input the caret sits one past the last character
+    assert_eq!(diagnose("(1 +"), "error: Expected atom, found end of input\n --> 1:5\n  |\n1 | (1 +\n  |     ^");
+    assert_eq!(diagnose("1 +\n"), "error: Expected atom, found end of input\n --> 2:1\n  |\n2 | \n  | ^");
+
+    // A span running past the end of its line is cut at the line break
+    let d = Diagnostic::new("Unclosed '('", Span::new(4, 20, 1, 5));
+    assert_eq!(d.render("1 + (2 *\n3"), "error: Unclosed '('\n --> 1:5\n  |\n1 | 1 + (2 *\n  |     ^^^^");
+}
+
+fn lex_one(src: &str) -> Result<TokenKind, String> {
+    match Lexer::new(src.to_string()).next_token() {
+        Ok(tokens) => Ok(tokens[0].kind.clone()),
+        Err(e) => Err(e.message),
+    }
+}
+
+#[test]
+fn numeric_literals() {
+    assert_eq!(lex_one("6.02e23"), Ok(TokenKind::Float(6.02e23)));
+    assert_eq!(lex_one("1E-9"), Ok(TokenKind::Float(1e-9)));
+    assert_eq!(lex_one("0xFF"), Ok(TokenKind::Int(255)));
+    assert_eq!(lex_one("0o755"), Ok(TokenKind::Int(0o755)));
+    assert_eq!(lex_one("0b1010"), Ok(TokenKind::Int(10)));
+    assert_eq!(lex_one("1_000_000"), Ok(TokenKind::Int(1_000_000)));
+    assert_eq!(lex_one(".5"), Ok(TokenKind::Float(0.5)));
+    assert_eq!(lex_one("18446744073709551616"), Ok(TokenKind::BigInt(&BigInt::from(1) << 64)));
+
+    assert_eq!(lex_one("1.2.3"), Err("Too many decimal points".to_string()));
+    assert_eq!(lex_one("0b102"), Err("Invalid digit '2' in binary literal".to_string()));
+    assert_eq!(lex_one("0x"), Err("Expected digits after '0x'".to_string()));
+    assert_eq!(lex_one("1e+"), Err("Expected digits in the exponent".to_string()));
+    assert_eq!(lex_one("1__0"), Err("Digit separator '_' must be between digits".to_string()));
+    assert_eq!(lex_one("1."), Err("Expected digits after the decimal point".to_string()));
+}
+
+fn eval(src: &str) -> Result<Value, String> {
+    let tokens = Lexer::new(src.to_string()).next_token().map_err(|e| e.message)?;
+    let ast = Perser::new(tokens).parser().map_err(|e| e.message)?;
+    let codes = Dis::new().dis(&ast).map_err(|e| e.message)?;
+    return VM::new(codes).run(&mut Env::new()).map_err(|e| e.to_string());
+}
+
+#[test]
+fn integer_operators() {
+    assert_eq!(eval("-7 % 3"), Ok(Value::Int(2)));
+    assert_eq!(eval("7 % -3"), Ok(Value::Int(-2)));
+    assert_eq!(eval("-7 // 2"), Ok(Value::Int(-4)));
+    assert_eq!(eval("0x10 | 6 xor 12 & 10"), Ok(Value::Int(0x10 | (6 ^ (12 & 10)))));
+    assert_eq!(eval("~0"), Ok(Value::Int(-1)));
+    assert_eq!(eval("1 + 1 << 4"), Ok(Value::Int(32)));
+    assert_eq!(eval("-16 >> 2"), Ok(Value::Int(-4)));
+
+    assert_eq!(eval("1 << (1 << 21)"), Err(RuntimeError::Overflow.to_string()));
+    assert_eq!(eval("7.5 % 2"), Err("Type error: '%' needs Int operands, found Float and Int".to_string()));
+    assert!(eval("~1.5").is_err());
+}
+
+fn run_error(src: &str) -> RuntimeError {
+    let tokens = Lexer::new(src.to_string()).next_token().ok().unwrap();
+    let ast = Perser::new(tokens).parser().ok().unwrap();
+    let codes = Dis::new().dis(&ast).ok().unwrap();
+    return VM::new(codes).run(&mut Env::new()).unwrap_err();
+}
+
+#[test]
+fn runtime_errors() {
+    let cases = [
+        ("1 / 0", RuntimeError::DivisionByZero, "Division by zero"),
+        ("1 % 0", RuntimeError::DivisionByZero, "Division by zero"),
+        ("7 // 0", RuntimeError::DivisionByZero, "Division by zero"),
+        ("2 ^ -1", RuntimeError::NegativeExponent, "Negative exponent for an integer power (use a float base, e.g. 2.0^-1)"),
+        ("2 ^ 2 ^ 40", RuntimeError::Overflow, "Integer overflow: the result would exceed 1048576 bits"),
+        ("2 ^ 9999999999", RuntimeError::Overflow, "Integer overflow: the result would exceed 1048576 bits"),
+        ("1 << (1 << 21)", RuntimeError::Overflow, "Integer overflow: the result would exceed 1048576 bits"),
+        ("1 << -1", RuntimeError::NegativeShift, "Negative shift amount"),
+        ("y", RuntimeError::UndefinedVariable("y".to_string()), "Undefined variable: y"),
+        ("_", RuntimeError::NoSuchResult("_".to_string()), "No result for _"),
+    ];
+    for (src, kind, message) in cases {
+        let e = run_error(src);
+        assert_eq!(e, kind, "{}", src);
+        assert_eq!(e.to_string(), message, "{}", src);
+    }
+
+    let mismatches = [
+        ("1 + true", "'+' is not defined for Int and Bool"),
+        ("true * 2.5", "'*' is not defined for Bool and Float"),
+        ("7.5 % 2", "'%' needs Int operands, found Float and Int"),
+        ("-true", "unary '-' is not defined for Bool"),
+        ("~1.5", "unary '~' is not defined for Float"),
+        ("for i in 1.5..3 {}", "for loop range needs Int bounds, found Float and Int"),
+    ];
+    for (src, detail) in mismatches {
+        let e = run_error(src);
+        assert_eq!(e, RuntimeError::TypeError(detail.to_string()), "{}", src);
+        assert_eq!(e.to_string(), format!("Type error: {}", detail));
+    }
+
+    // Bytecode the compiler never emits is reported, not executed
+    let bad = [
+        (vec![ByteCode::BINOP(0)], RuntimeError::StackUnderflow, "Stack underflow"),
+        (vec![ByteCode::PUSHI(1), ByteCode::PUSHI(2), ByteCode::BINOP(99)], RuntimeError::InvalidOpcode("BINOP 99".to_string()), "Invalid opcode: BINOP 99"),
+        (vec![ByteCode::PUSHF(1.0), ByteCode::UNARYOP(99)], RuntimeError::InvalidOpcode("UNARYOP 99".to_string()), "Invalid opcode: UNARYOP 99"),
+    ];
+    for (codes, kind, message) in bad {
+        let e = VM::new(ByteCodes { codes }).run(&mut Env::new()).unwrap_err();
+        assert_eq!(e, kind);
+        assert_eq!(e.to_string(), message);
+    }
+}
+
+fn show(src: &str) -> String {
+    match eval(src) {
+        Ok(v) => v.to_string(),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn big_integers() {
+    assert_eq!(show("2 ^ 64"), "18446744073709551616");
+    assert_eq!(show("factorial(30)"), "265252859812191058636308480000000");
+    assert_eq!(show("9223372036854775807 + 1"), "9223372036854775808");
+    assert_eq!(show("-(-9223372036854775807 - 1)"), "9223372036854775808");
+    assert_eq!(show("0x1_0000_0000_0000_0000"), "18446744073709551616");
+    assert_eq!(show("(2 ^ 100 + 1) % 1000007"), ((2i128.pow(100) + 1) % 1000007).to_string());
+    assert_eq!(show("-(2 ^ 70) // 3"), (-2i128.pow(70)).div_euclid(3).to_string());
+    assert_eq!(show("-(2 ^ 70) >> 3"), (-(2i128.pow(70)) >> 3).to_string());
+    assert_eq!(show("~(2 ^ 70) & (2 ^ 72 - 1)"), (!(2i128.pow(70)) & (2i128.pow(72) - 1)).to_string());
+    assert_eq!(show("isqrt(10 ^ 40)"), "100000000000000000000");
+    assert_eq!(show("gcd(2 ^ 80, 6 ^ 40)"), "1099511627776");
+
+    // results shrink back to Int, and mixing with Float gives a Float
+    assert_eq!(eval("2 ^ 64 - 2 ^ 64 + 1"), Ok(Value::Int(1)));
+    assert_eq!(eval("2 ^ 64 / 2 ^ 60"), Ok(Value::Int(16)));
+    assert_eq!(eval("2 ^ 64 * 0.5"), Ok(Value::Float(2f64.powi(63))));
+    assert_eq!(eval("2 ^ 64 > 2 ^ 63 && -(2 ^ 64) < 0"), Ok(Value::Bool(true)));
+    assert_eq!(eval("floor(1e20)"), Ok(Value::from(BigInt::parse("100000000000000000000", 10).unwrap())));
+    assert_eq!(show("2 ^ 64 / 0"), RuntimeError::DivisionByZero.to_string());
+    assert_eq!(show("2 ^ 2 ^ 40"), RuntimeError::Overflow.to_string());
+    assert_eq!(show("1 ^ (2 ^ 70) + (-1) ^ (2 ^ 70 + 1)"), "0");
+}
+
+#[test]
+fn bigint_matches_i128_arithmetic() {
+    // A fixed LCG keeps the operands reproducible; the shift varies their
+    // size from a few bits to 126 so sums and differences still fit i128.
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = || {
+        let mut draw = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            seed
+        };
+        let v = ((draw() as u128) << 64 | draw() as u128) as i128;
+        v >> (2 + draw() % 124)
+    };
+    let big = |i: i128| {
+        let m = BigInt::parse(&i.unsigned_abs().to_string(), 10).unwrap();
+        if i < 0 { -&m } else { m }
+    };
+    for _ in 0..2000 {
+        let (a, b) = (next(), next());
+        let (x, y) = (big(a), big(b));
+        assert_eq!(x.to_string(), a.to_string());
+        assert_eq!(x.cmp(&y), a.cmp(&b));
+        assert_eq!(&x + &y, big(a + b));
+        assert_eq!(&x - &y, big(a - b));
+        if let Some(p) = a.checked_mul(b) {
+            assert_eq!(&x * &y, big(p));
+        }
+        assert_eq!(&x & &y, big(a & b));
+        assert_eq!(&x | &y, big(a | b));
+        assert_eq!(&x ^ &y, big(a ^ b));
+        assert_eq!(!&x, big(!a));
+        assert_eq!(&x >> 37, big(a >> 37));
+        assert_eq!(x.to_f64(), a as f64);
+        if b != 0 {
+            assert_eq!(x.div_rem(&y), (big(a / b), big(a % b)));
+            let (q, r) = (a / b, a % b);
+            let floor = if r != 0 && (r < 0) != (b < 0) { (q - 1, r + b) } else { (q, r) };
+            assert_eq!(x.div_mod_floor(&y), (big(floor.0), big(floor.1)));
+        }
+    }
+}
+
+#[test]
+fn conditional_expressions() {
+    assert_eq!(eval("if 1 < 2 then 10 else 20"), Ok(Value::Int(10)));
+    assert_eq!(eval("1 > 2 ? 10 : 20"), Ok(Value::Int(20)));
+    assert_eq!(eval("1 + if false then 1 else 2 * 3"), Ok(Value::Int(7)));
+    assert_eq!(eval("false ? 1 : true ? 2 : 3"), Ok(Value::Int(2)));
+
+    // only the chosen branch runs
+    assert_eq!(eval("if true then 1 else 1 / 0"), Ok(Value::Int(1)));
+    assert_eq!(eval("false ? 1 / 0 : 2.5"), Ok(Value::Float(2.5)));
+
+    assert!(eval("if 1 then 2 else 3").is_err());
+    assert!(eval("if true then 2").is_err());
+}
+
+#[test]
+fn while_loops() {
+    assert_eq!(eval("{ i = 0; while i < 5 { i = i + 1 }; i }"), Ok(Value::Int(5)));
+    assert_eq!(eval("{ i = 0; while false { i = 1 }; i }"), Ok(Value::Int(0)));
+    assert_eq!(eval("{ n = 0; while true { n = n + 1; if n == 7 { break } } n }"), Ok(Value::Int(7)));
+    assert_eq!(eval("while false { 1 }"), Ok(Value::Unit));
+}
+
+#[test]
+fn for_loops() {
+    assert_eq!(eval("{ s = 0; for i in 1..11 { s = s + i }; s }"), Ok(Value::Int(55)));
+    assert_eq!(eval("{ s = 0; for i in 1..=10 { s = s + i }; s }"), Ok(Value::Int(55)));
+    assert_eq!(eval("{ s = 0; for i in 5..5 { s = s + 1 }; s }"), Ok(Value::Int(0)));
+    assert_eq!(eval("{ s = 0.0; for k in 0..20 { s = s + 1.0 / 2^k }; s > 1.99 }"), Ok(Value::Bool(true)));
+    // the loop variable keeps its last value
+    assert_eq!(eval("{ for i in 0..3 { }; i }"), Ok(Value::Int(2)));
+    assert!(eval("for i in 0..2.5 { }").is_err());
+}
+
+#[test]
+fn continue_skips_to_next_iteration() {
+    let src = "{ s = 0; for i in 0..10 { if i % 2 == 0 { continue }; s = s + i }; s }";
+    assert_eq!(eval(src), Ok(Value::Int(25)));
+    let src = "{ s = 0; i = 0; while i < 10 { i = i + 1; if i % 3 != 0 { continue }; s = s + i }; s }";
+    assert_eq!(eval(src), Ok(Value::Int(18)));
+}
+
+#[test]
+fn nested_loops_break_only_the_inner_loop() {
+    let src = "{
+        t = 0;
+        for a in 1..=3 {
+            for b in 1..=3 {
+                if b > a { break };
+                t = t + 10 * a + b
+            };
+            t = t + 100
+        };
+        t
+    }";
+    assert_eq!(eval(src), Ok(Value::Int(11 + 21 + 22 + 31 + 32 + 33 + 300)));
+
+    let src = "{
+        hits = 0;
+        i = 0;
+        while i < 4 {
+            i = i + 1;
+            for j in 0..100 {
+                if j == i { break };
+                if j == 0 { continue };
+                hits = hits + 1
+            }
+        };
+        hits
+    }";
+    assert_eq!(eval(src), Ok(Value::Int(6)));
+}
+
+#[test]
+fn break_and_continue_inside_expressions() {
+    // the pending `k +` operand is dropped before jumping
+    let src = "{ k = 0; for j in 0..10 { k = k + (if j % 2 == 0 then continue else j) }; k }";
+    assert_eq!(eval(src), Ok(Value::Int(25)));
+    let src = "{ k = 0; for j in 0..10 { k = k + (j == 4 ? break : j) }; k }";
+    assert_eq!(eval(src), Ok(Value::Int(6)));
+}
+
+#[test]
+fn loops_leave_the_stack_balanced() {
+    let src = "{ for a in 0..50 { for b in 0..50 { if b == a { break }; 1 + 2 } }; 0 }";
+    let tokens = Lexer::new(src.to_string()).next_token().ok().unwrap();
+    let ast = Perser::new(tokens).parser().ok().unwrap();
+    let mut vm = VM::new(Dis::new().dis(&ast).unwrap());
+    assert_eq!(vm.run(&mut Env::new()), Ok(Value::Int(0)));
+    assert!(vm.stack.is_empty());
+
+    // A loop after a loop in the same body, then a break from inside an
+    // expression that has to drop what the outer body left on the stack
+    let src = "{ n = 0; for a in 0..3 { for b in 0..2 { for c in 0..2 { n = n + 1 } }; 1 + 2 * if a == 1 { break } else { 3 } }; n }";
+    let tokens = Lexer::new(src.to_string()).next_token().ok().unwrap();
+    let ast = Perser::new(tokens).parser().ok().unwrap();
+    let mut vm = VM::new(Dis::new().dis(&ast).unwrap());
+    assert_eq!(vm.run(&mut Env::new()), Ok(Value::Int(8)));
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn break_outside_a_loop_is_a_syntax_error() {
+    assert_eq!(eval("break"), Err("'break' outside of a loop".to_string()));
+    assert_eq!(eval("{ continue }"), Err("'continue' outside of a loop".to_string()));
+    assert_eq!(eval("if true { break }"), Err("'break' outside of a loop".to_string()));
+
+    // The same trees built by hand are rejected by the compiler too,
+    // including inside a function body within a loop.
+    let span = Span::new(0, 5, 1, 1);
+    let stray = Expr { kind: ExprKind::Break, span };
+    match crate::compile(&stray) {
+        Err(crate::Error::Syntax(d)) => assert_eq!((d.message.as_str(), d.span), ("'break' outside of a loop", span)),
+        r => panic!("expected a syntax error, got {:?}", r.map(|_| ())),
+    }
+    let body = Expr { kind: ExprKind::Continue, span };
+    let def = Expr { kind: ExprKind::FnDef { name: "f".to_string(), params: vec![], body: Box::new(body) }, span };
+    let cond = Expr { kind: ExprKind::Bool(true), span };
+    let lp = Expr { kind: ExprKind::While { cond: Box::new(cond), body: Box::new(def) }, span };
+    assert!(crate::compile(&lp).is_err());
+}
+
+#[test]
+fn user_defined_functions() {
+    assert_eq!(eval("{ fn hyp(a, b) = (a^2 + b^2)^0.5; hyp(3, 4) }"), Ok(Value::Float(5.0)));
+    assert_eq!(eval("{ fn fact(n) = if n <= 1 then 1 else n * fact(n - 1); fact(20) }"), Ok(Value::Int(2432902008176640000)));
+    assert_eq!(eval("{ fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } } fib(15) }"), Ok(Value::Int(610)));
+    assert_eq!(eval("{ fn sum(n) { s = 0; for i in 1..=n { s = s + i }; s } sum(100) }"), Ok(Value::Int(5050)));
+    assert_eq!(eval("fn f(x) = x"), Ok(Value::Unit));
+}
+
+#[test]
+fn function_locals_do_not_leak() {
+    assert_eq!(eval("{ x = 1; fn f(x) { y = x * 2; y } f(5) + x }"), Ok(Value::Int(11)));
+    assert_eq!(eval("{ fn f(x) { y = x; y } f(5); y }"), Err("Undefined variable: y".to_string()));
+    assert_eq!(eval("{ fn g(x) { if x { z = 1 }; z } g(false) }"), Err("Undefined variable: z".to_string()));
+}
+
+#[test]
+fn call_errors() {
+    let arity = RuntimeError::ArityMismatch { name: "f".to_string(), expected: 1, found: 2 };
+    assert_eq!(eval("{ fn f(x) = x; f(1, 2) }"), Err(arity.to_string()));
+    assert_eq!(eval("3(4)"), Err("Int is not callable".to_string()));
+    assert_eq!(eval("fn f(a, a) = 1"), Err("Duplicate parameter 'a'".to_string()));
+
+    let src = "{ fn down(n) = if n == 0 then 0 else down(n - 1); down(50) }";
+    let tokens = Lexer::new(src.to_string()).next_token().ok().unwrap();
+    let codes = Dis::new().dis(&Perser::new(tokens).parser().ok().unwrap()).unwrap();
+    let mut env = Env::new();
+    env.max_depth = 20;
+    assert_eq!(VM::new(codes.clone()).run(&mut env), Err(RuntimeError::RecursionLimit(20)));
+    env.max_depth = 100;
+    assert_eq!(VM::new(codes).run(&mut env), Ok(Value::Int(0)));
+}
+
+#[test]
+fn builtin_functions() {
+    assert_eq!(eval("sqrt(16)"), Ok(Value::Float(4.0)));
+    assert_eq!(eval("cos(pi)"), Ok(Value::Float(-1.0)));
+    assert_eq!(eval("log(8, 2)"), Ok(Value::Float(3.0)));
+    assert_eq!(eval("floor(2.7) + ceil(2.2) + round(-2.5)"), Ok(Value::Int(2)));
+    assert_eq!(eval("max(1, 2.5, 2)"), Ok(Value::Float(2.5)));
+    assert_eq!(eval("min(3, -4, 7)"), Ok(Value::Int(-4)));
+    assert_eq!(eval("abs(-7)"), Ok(Value::Int(7)));
+    assert_eq!(eval("gcd(12, -18) * 10 + lcm(4, 6)"), Ok(Value::Int(72)));
+    assert_eq!(eval("factorial(20)"), Ok(Value::Int(2432902008176640000)));
+    assert_eq!(eval("isqrt(99)"), Ok(Value::Int(9)));
+    assert_eq!(eval("tau == 2 * pi"), Ok(Value::Bool(true)));
+
+    // globals shadow built-in names
+    assert_eq!(eval("{ e = 2; e + 1 }"), Ok(Value::Int(3)));
+    assert_eq!(eval("{ fn sin(x) = x; sin(5) }"), Ok(Value::Int(5)));
+
+    let arity = RuntimeError::ArityMismatch { name: "atan2".to_string(), expected: 2, found: 1 };
+    assert_eq!(eval("atan2(1)"), Err(arity.to_string()));
+    assert_eq!(eval("sqrt(true)"), Err("Type error: sqrt expects a number, found Bool".to_string()));
+    assert_eq!(eval("gcd(1.5, 2)"), Err("Type error: gcd expects an Int, found Float".to_string()));
+    assert_eq!(eval("factorial(10 ^ 6)"), Err(RuntimeError::Overflow.to_string()));
+    assert_eq!(eval("factorial(-1)"), Err("Invalid argument: factorial of a negative number".to_string()));
+}
+
+#[test]
+fn comparison_and_logic() {
+    assert_eq!(eval("1 + 1 == 2 && 3 > 2"), Ok(Value::Bool(true)));
+    assert_eq!(eval("2.0 >= 2"), Ok(Value::Bool(true)));
+    assert_eq!(eval("!(1 < 2) || false"), Ok(Value::Bool(false)));
+
+    // the right operand would divide by zero if it were evaluated
+    assert_eq!(eval("true || 1 / 0 == 1"), Ok(Value::Bool(true)));
+    assert_eq!(eval("false && 1 / 0 == 1"), Ok(Value::Bool(false)));
+
+    assert!(eval("true && 1").is_err());
+    assert!(eval("1 < 2 < 3").is_err());
+}
+
+// cargo test --release -- --ignored --nocapture bench_lex_large_input
+#[test]
+#[ignore]
+fn bench_lex_large_input() {
+    for mb in [1, 4] {
+        let mut src = String::new();
+        while src.len() < mb << 20 {
+            src.push_str("(x_1 + 2.5) * 3 - 4 / π + ");
+        }
+        src.push('1');
+
+        let start = std::time::Instant::now();
+        let tokens = Lexer::new(src).next_token().ok().unwrap();
+        println!("{} MB: {} tokens in {:?}", mb, tokens.len(), start.elapsed());
+    }
+}
+
+// cargo test --release -- --ignored --nocapture bench_nested_expression
+#[test]
+#[ignore]
+fn bench_nested_expression() {
+    let src = nested_expression(200);
+    let tokens = Lexer::new(src).next_token().ok().unwrap();
+    let ast = Perser::new(tokens).parser().ok().unwrap();
+    let codes = Dis::new().dis(&ast).unwrap();
+    let mut env = Env::new();
+
+    let runs = 20_000;
+    let start = std::time::Instant::now();
+    for _ in 0..runs {
+        VM::new(codes.clone()).run(&mut env).unwrap();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{} instructions x {} runs: {:?} ({:?}/run)",
+        codes.codes.len(),
+        runs,
+        elapsed,
+        elapsed / runs
+    );
+}
+
+#[test]
+fn engine_keeps_state_between_evals() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("x = 6 * 7").ok(), Some(Value::Int(42)));
+    assert_eq!(engine.eval("fn twice(n) = 2 * n").ok(), Some(Value::Unit));
+    assert_eq!(engine.eval("twice(x) + _").ok(), Some(Value::Int(126)));
+    assert_eq!(engine.global("x"), Some(Value::Int(42)));
+    assert_eq!(engine.global("y"), None);
+
+    let err = engine.eval("(1 +").unwrap_err();
+    assert!(err.render("(1 +").starts_with("error: Expected atom, found end of input"));
+    let err = engine.eval("1 / 0").unwrap_err();
+    assert_eq!(err.to_string(), RuntimeError::DivisionByZero.to_string());
+}
+
+#[test]
+fn previous_results() {
+    let mut engine = Engine::new();
+    let no_result = |e: crate::Error| match e {
+        crate::Error::Runtime(RuntimeError::NoSuchResult(name)) => name,
+        e => panic!("expected NoSuchResult, got {:?}", e),
+    };
+    assert_eq!(no_result(engine.eval("_").unwrap_err()), "_");
+    assert_eq!(engine.eval("ans").unwrap_err().to_string(), "No result for ans");
+
+    assert_eq!(engine.eval("6 * 7").ok(), Some(Value::Int(42)));
+    assert_eq!(engine.eval("_ + 1").ok(), Some(Value::Int(43)));
+    assert_eq!(engine.eval("ans * 2").ok(), Some(Value::Int(86)));
+    // Unit results and errors leave the history alone
+    assert_eq!(engine.eval("fn f(y) = y").ok(), Some(Value::Unit));
+    assert!(engine.eval("1 / 0").is_err());
+    assert_eq!(engine.eval("_1 + _2 + _3").ok(), Some(Value::Int(171)));
+    assert_eq!(engine.eval("_4 - _").ok(), Some(Value::Int(0)));
+
+    assert_eq!(no_result(engine.eval("_6").unwrap_err()), "_6");
+    assert_eq!(no_result(engine.eval("_0").unwrap_err()), "_0");
+    assert_eq!(engine.eval("_9").unwrap_err().to_string(), "No result for _9");
+    assert_eq!(eval("_ + 1"), Err("No result for _".to_string()));
+
+    // They name results, not variables
+    let err = engine.eval("_1 = 2").unwrap_err();
+    assert!(err.render("_1 = 2").starts_with("error: Cannot assign to '_1', it refers to a previous result"));
+    assert_eq!(engine.eval("_x = 2").ok(), Some(Value::Int(2)));
+    assert_eq!(engine.eval("_x * 2.5").ok(), Some(Value::Float(5.0)));
+}
+
+#[test]
+fn host_functions_and_globals() {
+    let mut engine = Engine::new();
+    engine.set_global("rate", 0.25);
+    engine.set_global("limit", 100);
+    engine.register("tax", &[Type::Float], |a| Ok(Value::Float(a[0].as_f64().unwrap() * 0.25)));
+    engine.register("clamp", &[Type::Int], |a| Ok(Value::Int(a[0].as_i64().unwrap().min(100))));
+    engine.register_variadic("all", &[Type::Bool], |a| Ok(Value::Bool(a.iter().all(|b| b.as_bool() == Some(true)))));
+
+    assert_eq!(engine.eval("tax(8)").ok(), Some(Value::Float(2.0)));
+    assert_eq!(engine.eval("clamp(250) == limit").ok(), Some(Value::Bool(true)));
+    assert_eq!(engine.eval("all(true, 1 < 2, rate > 0)").ok(), Some(Value::Bool(true)));
+
+    let err = engine.eval("clamp(2.5)").unwrap_err();
+    assert_eq!(err.to_string(), "Type error: clamp expects an Int, found Float");
+    let err = engine.eval("tax(1, 2)").unwrap_err();
+    let arity = RuntimeError::ArityMismatch { name: "tax".to_string(), expected: 1, found: 2 };
+    assert_eq!(err.to_string(), arity.to_string());
+    let err = engine.eval("all()").unwrap_err();
+    let arity = RuntimeError::ArityMismatch { name: "all".to_string(), expected: 1, found: 0 };
+    assert_eq!(err.to_string(), arity.to_string());
+
+    // a host function may replace a built-in
+    engine.register("sqrt", &[Type::Any], |_| Ok(Value::Int(0)));
+    assert_eq!(engine.eval("sqrt(9)").ok(), Some(Value::Int(0)));
+}
+
+#[test]
+fn exact_mode_keeps_fractions() {
+    let mut engine = Engine::new();
+    let mut show = |src: &str| match engine.eval(src) {
+        Ok(v) => v.to_string(),
+        Err(e) => e.to_string(),
+    };
+    assert_eq!(show("7 / 2"), "3");
+    assert_eq!(show("1/3 + 1/3 + 1/3 == 1"), "false");
+
+    let mut engine = Engine::new();
+    engine.set_exact(true);
+    let mut show = |src: &str| match engine.eval(src) {
+        Ok(v) => v.to_string(),
+        Err(e) => e.to_string(),
+    };
+    assert_eq!(show("7 / 2"), "7/2");
+    assert_eq!(show("1/3 + 1/3 + 1/3"), "1");
+    assert_eq!(show("6 / -4"), "-3/2");
+    assert_eq!(show("2 ^ -3 + (1/2) ^ 2"), "3/8");
+    assert_eq!(show("(7/2) // 1 + (7/2) % 1"), "7/2");
+    assert_eq!(show("floor(-7/2) + ceil(1/3) + round(5/2)"), "0");
+    assert_eq!(show("max(1/3, 0.3, 1/4)"), "1/3");
+    assert_eq!(show("1/3 < 0.34 && 2/4 == 1/2"), "true");
+    assert_eq!(show("1/3 * 1.5"), "0.5");
+    assert_eq!(show("1 / 0"), RuntimeError::DivisionByZero.to_string());
+    assert_eq!(show("15 ^ 9223372036854775807"), RuntimeError::Overflow.to_string());
+    assert_eq!(show("(15/2) ^ 9223372036854775807"), RuntimeError::Overflow.to_string());
+    assert_eq!(show("31 ^ 4611686018427387904"), RuntimeError::Overflow.to_string());
+    assert_eq!(show("(-1) ^ 8589934592 + 1 ^ -8589934593"), "2");
+    assert_eq!(show("0 ^ -8589934592"), RuntimeError::DivisionByZero.to_string());
+    assert_eq!(show("(1/2) & 1"), "Type error: '&' needs Int operands, found Rational and Int");
+
+    let r = Rational::new(BigInt::from(-7), BigInt::from(2)).unwrap();
+    assert_eq!((r.to_string(), r.mixed()), ("-7/2".to_string(), "-3 1/2".to_string()));
+    assert_eq!(Rational::new(BigInt::from(1), BigInt::from(-3)).unwrap().mixed(), "-1/3");
+}
+
+#[test]
+fn decimal_arithmetic() {
+    assert_eq!(show("0.1d + 0.2d"), "0.3");
+    assert_eq!(show("0.1d + 0.2d == 0.3d"), "true");
+    assert_eq!(show("0.1 + 0.2 == 0.3"), "false");
+    assert_eq!(show("1.10d * 2"), "2.20");
+    assert_eq!(show("1d / 3"), "0.3333333333333333333333333333");
+    assert_eq!(show("2d / 3"), "0.6666666666666666666666666667");
+    assert_eq!(show("1d / 4"), "0.25");
+    assert_eq!(show("1.5e3d + 1"), "1501");
+    assert_eq!(show("1.5e-3d"), "0.0015");
+    assert_eq!(show("2d ^ -2"), "0.25");
+    assert_eq!(show("1.1d ^ 2.0d"), "1.21");
+    assert_eq!(Decimal::parse("1.0"), Decimal::parse("1.00"));
+    assert_eq!(eval("1.0d * 1"), eval("1.00d"));
+    assert_ne!(Decimal::parse("1.0"), Decimal::parse("1.01"));
+    assert_eq!(show("4d ^ -1.00d"), "0.25");
+    assert_eq!(show("4d ^ 0.5d"), "2");
+    assert_eq!(show("-7.5d // 2 + -7.5d % 2"), "-3.5");
+    assert_eq!(show("round(2.5d) + floor(-0.5d) + trunc(-1.9d) + ceil(0.1d)"), "2");
+    assert_eq!(show("abs(-0.10d)"), "0.10");
+    assert_eq!(show("0.5d + 1/2 * 0"), "0.5");
+    assert_eq!(show("0.5d * 1.0"), "0.5");
+    assert_eq!(show("1d / 0"), RuntimeError::DivisionByZero.to_string());
+    assert_eq!(show("1.5d & 1"), "Type error: '&' needs Int operands, found Decimal and Int");
+
+    let mut engine = Engine::new();
+    engine.set_decimal(true);
+    engine.set_precision(5);
+    let mut show = |src: &str| match engine.eval(src) {
+        Ok(v) => v.to_string(),
+        Err(e) => e.to_string(),
+    };
+    assert_eq!(show("0.1 + 0.2"), "0.3");
+    assert_eq!(show("2 / 3"), "0.66667");
+    assert_eq!(show("2 ^ 20"), "1048576");
+    assert_eq!(show("123456789 * 1.0"), "123460000");
+
+    // Past MAX_SCALE digits every division would overflow, slowly
+    let mut big = Engine::new();
+    big.set_precision(u32::MAX);
+    assert_eq!(big.context().precision, MAX_PRECISION);
+    big.set_precision(0);
+    assert_eq!(big.context().precision, 1);
+
+    for (mode, half, neg) in [
+        (Rounding::HalfEven, "0.6667", "-2"),
+        (Rounding::HalfUp, "0.6667", "-3"),
+        (Rounding::Down, "0.6666", "-2"),
+    ] {
+        let ctx = Context { precision: 4, rounding: mode };
+        let two = Decimal::from(BigInt::from(2));
+        let three = Decimal::from(BigInt::from(3));
+        assert_eq!(two.div(&three, &ctx).unwrap().to_string(), half);
+        assert_eq!(Decimal::parse("-2.5").unwrap().to_integer(mode).to_string(), neg);
+    }
+}
+
+#[test]
+fn complex_numbers() {
+    assert_eq!(show("sqrt(-1)"), "1i");
+    assert_eq!(show("sqrt(-4) == 2i"), "true");
+    assert_eq!(show("(3+4i) * (1-2i)"), "11-2i");
+    assert_eq!(show("(3+4i) / (1-2i)"), "-1+2i");
+    assert_eq!(show("abs(3+4i)"), "5");
+    assert_eq!(show("arg(-1) == pi && arg(2i) == pi / 2"), "true");
+    assert_eq!(show("conj(3+4i)"), "3-4i");
+    assert_eq!(show("re(3+4i) + im(3+4i) + im(7)"), "7");
+    assert_eq!(show("abs(exp(i * pi) + 1) < 1e-15"), "true");
+    assert_eq!(show("i ^ 2"), "-1");
+    assert_eq!(show("(1+i) ^ 2"), "2i");
+    assert_eq!(show("ln(-1)"), "3.141592653589793i");
+    assert_eq!(show("asin(2)"), "1.5707963267948966+1.3169578969248164i");
+    assert_eq!(show("abs((-8.0) ^ (1/3.0) - (1 + sqrt(3) * i)) < 1e-15"), "true");
+    assert_eq!(show("-2.5i"), "-2.5i");
+    assert_eq!(show("2i < 3i"), "Type error: '<' is not defined for Complex and Complex");
+    assert_eq!(show("2i % 2"), "Type error: '%' is not defined for Complex and Int");
+
+    // `i` is only the imaginary unit until a variable takes the name
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("i = 3").ok(), Some(Value::Int(3)));
+    assert_eq!(engine.eval("i * i").ok(), Some(Value::Int(9)));
+}
+
+#[test]
+fn units_and_conversion() {
+    assert_eq!(show("3 m + 20 cm"), "3.2 m");
+    assert_eq!(show("20 cm + 3 m"), "320 cm");
+    assert_eq!(show("60 km/h in m/s"), "16.6666666666667 m/s");
+    assert_eq!(show("5 kg * 9.81 m/s^2"), "49.05 N");
+    assert_eq!(show("10 m / 2 s"), "5 m/s");
+    assert_eq!(show("2 m * 3 m"), "6 m^2");
+    assert_eq!(show("(4 m^2) ^ 0.5"), "2 m");
+    assert_eq!(show("6 m / 2 m"), "3");
+    assert_eq!(show("2 ft to inch"), "24 inch");
+    assert_eq!(show("2^3 m"), "8 m");
+    assert_eq!(show("1/2.0 m"), "0.5 m");
+    let mut exact = Engine::new();
+    exact.set_exact(true);
+    assert_eq!(exact.eval("1/2 m").map(|v| v.to_string()).ok(), Some("0.5 m".to_string()));
+    assert_eq!(show("-2 m"), "-2 m");
+    assert_eq!(show("10 m / 2 s * 3 s"), "15 m");
+    assert_eq!(show("2 * 3 * 4 / 2 m"), "12 m");
+    assert_eq!(show("12 m / 2 / 3"), "2 m");
+    assert_eq!(show("x = 10 m; x / 2 s"), "5 m/s");
+    assert_eq!(show("fn v(d) = d / 4 s; v(100 m)"), "25 m/s");
+    assert_eq!(show("(10 m) / 2 s"), "5 m/s");
+    assert_eq!(show("x = 3; x * 3 / 2.0 m"), "4.5 m");
+    match eval("1 ft in inch") {
+        Ok(Value::Quantity(q)) => assert_eq!(q.value, 0.3048 / 0.0254),
+        v => panic!("expected a quantity, got {:?}", v),
+    }
+    match eval("1 km in mi in km") {
+        Ok(Value::Quantity(q)) => assert!((q.value - 1.0).abs() < 1e-15),
+        v => panic!("expected a quantity, got {:?}", v),
+    }
+    assert_eq!(show("1 kW * 2 h in kJ"), "7200 kJ");
+    assert_eq!(show("1 km > 999 m"), "true");
+    assert_eq!(show("-(2 lb) * 2"), "-4 lb");
+    assert_eq!(show("1 m + 1 s"), "Dimension error: '+' needs the same dimension on both sides, found m and s");
+    assert_eq!(show("1 + 1 s"), "Dimension error: '+' needs the same dimension on both sides, found a number and s");
+    assert_eq!(show("1 m in s"), "Dimension error: cannot convert m to s");
+    assert_eq!(show("(2 m) ^ 0.5"), "Dimension error: m to the power 0.5 is not a unit");
+    assert_eq!(show("1 m in parsec"), "Unknown unit 'parsec'");
+
+    // Unit powers are i32s; leaving that range is an error, not a wrap
+    assert_eq!(show("1 m^2000000000 * 1 m^2000000000"), "Dimension error: unit power out of range in m^2000000000 * m^2000000000");
+    assert_eq!(show("(1 m) ^ 3000000000 * 1 m"), "Dimension error: unit power out of range in m ^ 3000000000");
+    assert_eq!(show("(1 m^2) ^ 2000000000"), "Dimension error: unit power out of range in m^2 ^ 2000000000");
+    assert_eq!(show("(1 m) ^ -2147483648"), "1 1/m^2147483648");
+    assert_eq!(show("1 m^99999999999"), "Unit power is too large");
+    assert_eq!(show("1 m^2000000000*m^2000000000"), "Unit power is too large");
+    assert_eq!(show("1 s in s^2000000000/s^-2000000000"), "Unit power is too large");
+
+    // Units only follow numbers, and a compound unit is written without
+    // spaces or inside parentheses; elsewhere the names are free for variables
+    let tokens = Lexer::new("2 m*x + m".to_string()).next_token().unwrap();
+    let kinds: Vec<String> = tokens.iter().map(|t| format!("{:?}", t.kind)).collect();
+    assert!(kinds[1].starts_with("Unit"));
+    assert_eq!(&kinds[2..], ["Mul", "Ident(\"x\")", "Add", "Ident(\"m\")", "EOF"]);
+    assert_eq!(show("s = 2; 6 m / s"), "3 m");
+    assert_eq!(show("g = 4; 8 kg / g"), "2 kg");
+    assert_eq!(show("6 (m / s) + 1 m/s"), "7 m/s");
+    assert_eq!(show("36 ( km / h ) in m/s"), "10 m/s");
+    assert_eq!(show("1 m ^ 2"), "A unit power is written without spaces, as in 'm^2'");
+    assert_eq!(
+        diagnose("5 kg * 9.81 m/s ^ 2"),
+        "error: A unit power is written without spaces, as in 'm^2'\n --> 1:17\n  |\n1 | 5 kg * 9.81 m/s ^ 2\n  |                 ^"
+    );
+    assert_eq!(show("(1 m) ^ 2"), "1 m^2");
+}
+
+#[test]
+fn statements_and_newlines() {
+    assert_eq!(show("x = 1; x + 1"), "2");
+    assert_eq!(show("x = 1\ny = 2\n\nx + y"), "3");
+    assert_eq!(show("\n1 +\n2\n"), "3");
+    assert_eq!(show("(1\n+ 2)"), "3");
+    assert_eq!(show("if false then 1\nelse 2"), "2");
+    assert_eq!(show("true\n? 1\n: 2"), "1");
+    assert_eq!(show("{\n  a = 1\n  a + 1\n}"), "2");
+    assert_eq!(show("s = 0\nfor i in 1..=3\n{\n  s = s + i\n}\ns"), "6");
+    assert_eq!(eval("x = 1;"), Ok(Value::Unit));
+    assert_eq!(eval(""), Ok(Value::Unit));
+    assert_eq!(show("1 2"), "Expected ';', a new line or end of input, found '2'");
+    assert_eq!(show("{ 1 2 }"), "Expected ';', a new line or '}', found '2'");
+}
+
+#[test]
+fn inspecting_the_pipeline() {
+    let ast = |src: &str| Perser::new(Lexer::new(src.to_string()).next_token().unwrap()).parser().unwrap();
+    assert_eq!(ast("1 + 2 * x").repr(), "Program(Binary(Add,Int(1),Binary(Mul,Int(2),Var(x))))");
+    assert_eq!(ast("y = -1; { y }").repr(), "Program(Assign(y,Unary(Neg,Int(1)));Block(Var(y)))");
+
+    let listing = Dis::new().dis(&ast("fn sq(x) = x * x")).unwrap().dis();
+    assert!(listing.starts_with("   0 PUSHFN sq\n   1 STORE sq\n"));
+    assert!(listing.ends_with("fn sq(x):\n   0 LOAD_LOCAL 0\n   1 LOAD_LOCAL 0\n   2 BINOP *\n   3 RET\n"));
+
+    let steps = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let log = steps.clone();
+    let mut engine = Engine::new();
+    let v = engine.trace("1 + 2 == 3", move |code, stack| {
+        let stack: Vec<String> = stack.iter().map(|v| v.to_string()).collect();
+        log.borrow_mut().push(format!("{} [{}]", code, stack.join(", ")));
+    });
+    assert_eq!(v.ok(), Some(Value::Bool(true)));
+    assert_eq!(*steps.borrow(), ["PUSHI 1 [1]", "PUSHI 2 [1, 2]", "BINOP + [3]", "PUSHI 3 [3, 3]", "BINOP == [true]"]);
+}
+
+#[test]
+fn long_chains_and_deep_nesting() {
+    // 200k terms is a few hundred kilobytes of generated source
+    let sum = vec!["1"; 200_000].join("+");
+    assert_eq!(eval(&sum), Ok(Value::Int(200_000)));
+    let all = vec!["1 < 2"; 50_000].join(" && ");
+    assert_eq!(eval(&all), Ok(Value::Bool(true)));
+    let body = format!("{{ fn f(x) {{ y = {}; y }} f(1) }}", vec!["x"; 50_000].join(" - "));
+    assert_eq!(eval(&body), Ok(Value::Int(1 - 49_999)));
+
+    // The deepest input the parser accepts has to fit in the 8 MB a main
+    // thread usually gets, even in a debug build; a test thread has less.
+    let nested = std::thread::Builder::new().stack_size(8 << 20).spawn(|| {
+        let deepest = |open: &str, close: &str| format!("{}1{}", open.repeat(127), close.repeat(127));
+        let limit = "Expression nested too deeply, the limit is 128 levels".to_string();
+        assert_eq!(eval(&deepest("(", ")")), Ok(Value::Int(1)));
+        assert_eq!(eval(&deepest("{", "}")), Ok(Value::Int(1)));
+        assert_eq!(eval(&deepest("if true {", "}")), Ok(Value::Unit));
+        assert_eq!(eval(&deepest("if false then 1 else ", "")), Ok(Value::Int(1)));
+        assert_eq!(eval(&deepest("false ? 0 : ", "")), Ok(Value::Int(1)));
+        assert_eq!(eval(&deepest("while false {", "}")), Ok(Value::Unit));
+        assert_eq!(eval(&format!("fn f(x) = x; {}", deepest("f(", ")"))), Ok(Value::Int(1)));
+        assert_eq!(eval(&format!("{}1{}", "(".repeat(128), ")".repeat(128))), Err(limit.clone()));
+        assert_eq!(eval(&format!("{}1{}", "(".repeat(5000), ")".repeat(5000))), Err(limit.clone()));
+        assert_eq!(eval(&format!("{}1", "-".repeat(5000))), Err(limit.clone()));
+        assert_eq!(eval(&format!("{}1", "2^".repeat(5000))), Err(limit.clone()));
+        assert_eq!(eval(&format!("{}1{}", "{".repeat(5000), "}".repeat(5000))), Err(limit.clone()));
+        assert_eq!(eval(&format!("{}1", "if false then 1 else ".repeat(250))), Err(limit.clone()));
+        assert_eq!(eval(&format!("{}1", "false ? 0 : ".repeat(100_000))), Err(limit.clone()));
+        assert_eq!(eval(&format!("{}1{}", "true ? ".repeat(100_000), " : 0".repeat(100_000))), Err(limit));
+    });
+    nested.unwrap().join().unwrap();
+}
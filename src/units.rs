@@ -0,0 +1,234 @@
+/// Powers of the SI base dimensions, in the order length, mass, time,
+/// current, temperature, amount of substance and luminous intensity.
+pub type Dims = [i32; 7];
+
+const NONE: Dims = [0; 7];
+const LENGTH: Dims = [1, 0, 0, 0, 0, 0, 0];
+const MASS: Dims = [0, 1, 0, 0, 0, 0, 0];
+const TIME: Dims = [0, 0, 1, 0, 0, 0, 0];
+const AREA: Dims = [2, 0, 0, 0, 0, 0, 0];
+const VOLUME: Dims = [3, 0, 0, 0, 0, 0, 0];
+const SPEED: Dims = [1, 0, -1, 0, 0, 0, 0];
+const FORCE: Dims = [1, 1, -2, 0, 0, 0, 0];
+const PRESSURE: Dims = [-1, 1, -2, 0, 0, 0, 0];
+const ENERGY: Dims = [2, 1, -2, 0, 0, 0, 0];
+const POWER: Dims = [2, 1, -3, 0, 0, 0, 0];
+
+// Symbol, size in SI base units, dimensions, and whether SI prefixes
+// apply. Prefixes go on the gram rather than the kilogram.
+const UNITS: [(&str, f64, Dims, bool); 35] = [
+    ("m", 1.0, LENGTH, true),
+    ("g", 1e-3, MASS, true),
+    ("s", 1.0, TIME, true),
+    ("A", 1.0, [0, 0, 0, 1, 0, 0, 0], true),
+    ("K", 1.0, [0, 0, 0, 0, 1, 0, 0], true),
+    ("mol", 1.0, [0, 0, 0, 0, 0, 1, 0], true),
+    ("cd", 1.0, [0, 0, 0, 0, 0, 0, 1], true),
+    ("Hz", 1.0, [0, 0, -1, 0, 0, 0, 0], true),
+    ("N", 1.0, FORCE, true),
+    ("Pa", 1.0, PRESSURE, true),
+    ("J", 1.0, ENERGY, true),
+    ("W", 1.0, POWER, true),
+    ("C", 1.0, [0, 0, 1, 1, 0, 0, 0], true),
+    ("V", 1.0, [2, 1, -3, -1, 0, 0, 0], true),
+    ("ohm", 1.0, [2, 1, -3, -2, 0, 0, 0], true),
+    ("F", 1.0, [-2, -1, 4, 2, 0, 0, 0], true),
+    ("S", 1.0, [-2, -1, 3, 2, 0, 0, 0], true),
+    ("Wb", 1.0, [2, 1, -2, -1, 0, 0, 0], true),
+    ("T", 1.0, [0, 1, -2, -1, 0, 0, 0], true),
+    ("H", 1.0, [2, 1, -2, -2, 0, 0, 0], true),
+    ("L", 1e-3, VOLUME, true),
+    ("eV", 1.602176634e-19, ENERGY, true),
+    ("min", 60.0, TIME, false),
+    ("h", 3600.0, TIME, false),
+    ("day", 86400.0, TIME, false),
+    ("ha", 1e4, AREA, false),
+    // US customary and imperial
+    ("inch", 0.0254, LENGTH, false),
+    ("ft", 0.3048, LENGTH, false),
+    ("yd", 0.9144, LENGTH, false),
+    ("mi", 1609.344, LENGTH, false),
+    ("oz", 0.028349523125, MASS, false),
+    ("lb", 0.45359237, MASS, false),
+    ("gal", 3.785411784e-3, VOLUME, false),
+    ("mph", 0.44704, SPEED, false),
+    ("psi", 6894.757293168361, PRESSURE, false),
+];
+
+const PREFIXES: [(&str, f64); 21] = [
+    ("Y", 1e24),
+    ("Z", 1e21),
+    ("E", 1e18),
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("da", 1e1),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+    ("f", 1e-15),
+    ("a", 1e-18),
+    ("z", 1e-21),
+    ("y", 1e-24),
+];
+
+// Products of other units that are worth a name of their own: a product
+// with exactly these dimensions is shown in this unit instead.
+const DERIVED: [&str; 11] = ["N", "Pa", "J", "W", "C", "V", "ohm", "F", "S", "Wb", "T"];
+
+/// A unit of measurement such as `km/h`: its dimensions and its size in SI
+/// base units, along with the symbols it is written with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unit {
+    pub dims: Dims,
+    pub scale: f64, // 1000 for km, 1/3.6 for km/h
+    factors: Vec<(String, i32)>, // symbols and their powers, for printing
+}
+
+impl Unit {
+    /// The unit a plain number has.
+    pub fn none() -> Unit {
+        return Unit { dims: NONE, scale: 1.0, factors: Vec::new() };
+    }
+
+    /// A unit from the table, possibly with an SI prefix: `m`, `km`, `lb`.
+    pub fn lookup(symbol: &str) -> Option<Unit> {
+        let unit = |scale: f64, dims: Dims| Unit { dims, scale, factors: vec![(symbol.to_string(), 1)] };
+        if let Some((_, scale, dims, _)) = UNITS.iter().find(|u| u.0 == symbol) {
+            return Some(unit(*scale, *dims));
+        }
+        for (prefix, factor) in PREFIXES {
+            if let Some(rest) = symbol.strip_prefix(prefix)
+                && let Some((_, scale, dims, _)) = UNITS.iter().find(|u| u.0 == rest && u.3)
+            {
+                return Some(unit(scale * factor, *dims));
+            }
+        }
+        return None;
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        return self.dims == NONE;
+    }
+
+    /// The product of two units; None if a power leaves the range of
+    /// an i32, as `m^2000000000 * m^2000000000` would.
+    pub fn mul(&self, other: &Unit) -> Option<Unit> {
+        let mut dims = self.dims;
+        for (d, o) in dims.iter_mut().zip(other.dims) {
+            *d = d.checked_add(o)?;
+        }
+        let mut factors = self.factors.clone();
+        for (symbol, power) in &other.factors {
+            match factors.iter_mut().find(|(s, _)| s == symbol) {
+                Some((_, p)) => *p = p.checked_add(*power)?,
+                None => factors.push((symbol.clone(), *power)),
+            }
+        }
+        factors.retain(|(_, p)| *p != 0);
+        return Some(Unit { dims, scale: self.scale * other.scale, factors });
+    }
+
+    pub fn div(&self, other: &Unit) -> Option<Unit> {
+        return self.mul(&other.powi(-1)?);
+    }
+
+    pub fn powi(&self, n: i32) -> Option<Unit> {
+        let mut dims = self.dims;
+        for d in dims.iter_mut() {
+            *d = d.checked_mul(n)?;
+        }
+        let mut factors = Vec::new();
+        for (s, p) in &self.factors {
+            factors.push((s.clone(), p.checked_mul(n)?));
+        }
+        return Some(Unit { dims, scale: self.scale.powi(n), factors });
+    }
+
+    /// A fractional power such as the square root of `m^2`; None unless
+    /// every power comes out whole and within the range of an i32.
+    pub fn powf(&self, e: f64) -> Option<Unit> {
+        let whole = |p: i32| {
+            let q = p as f64 * e;
+            if q.fract() != 0.0 || q < i32::MIN as f64 || q > i32::MAX as f64 {
+                return None;
+            }
+            return Some(q as i32);
+        };
+        let mut dims = self.dims;
+        for d in dims.iter_mut() {
+            *d = whole(*d)?;
+        }
+        let mut factors = Vec::new();
+        for (s, p) in &self.factors {
+            factors.push((s.clone(), whole(*p)?));
+        }
+        return Some(Unit { dims, scale: self.scale.powf(e), factors });
+    }
+
+    /// The named SI unit with the same dimensions, if this is a compound
+    /// such as `kg*m/s^2` that has one (`N`).
+    pub fn derived(&self) -> Option<Unit> {
+        if self.factors.len() < 2 {
+            return None;
+        }
+        return DERIVED.iter().filter_map(|s| Unit::lookup(s)).find(|u| u.dims == self.dims);
+    }
+}
+
+// `m`, `m/s^2`, `kg*m^2/s^2`, `1/s`.
+impl std::fmt::Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let power = |s: &str, p: u32| if p == 1 { s.to_string() } else { format!("{}^{}", s, p) };
+        let num: Vec<String> = self.factors.iter().filter(|(_, p)| *p > 0).map(|(s, p)| power(s, p.unsigned_abs())).collect();
+        let den: Vec<String> = self.factors.iter().filter(|(_, p)| *p < 0).map(|(s, p)| power(s, p.unsigned_abs())).collect();
+        let mut s = if num.is_empty() { "1".to_string() } else { num.join("*") };
+        for d in den {
+            s = format!("{}/{}", s, d);
+        }
+        write!(f, "{}", s)
+    }
+}
+
+/// A number with a unit, such as `9.81 m/s^2`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quantity {
+    pub value: f64, // in `unit`, not in SI base units
+    pub unit: Unit,
+}
+
+impl Quantity {
+    /// The value in SI base units.
+    pub fn si(&self) -> f64 {
+        return self.value * self.unit.scale;
+    }
+
+    /// The same quantity expressed in `unit`; None if the dimensions differ.
+    pub fn to(&self, unit: &Unit) -> Option<Quantity> {
+        if unit.dims != self.unit.dims {
+            return None;
+        }
+        if *unit == self.unit {
+            return Some(self.clone());
+        }
+        let value = self.value * (self.unit.scale / unit.scale);
+        return Some(Quantity { value, unit: unit.clone() });
+    }
+}
+
+// Factors such as 0.3048 m per ft are inexact in binary, so the value is
+// shown to 15 significant digits: 2 ft is 24 inch rather than
+// 24.000000000000004 inch. The value itself keeps every digit.
+impl std::fmt::Display for Quantity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let shown: f64 = format!("{:.14e}", self.value).parse().unwrap_or(self.value);
+        write!(f, "{} {}", shown, self.unit)
+    }
+}
@@ -0,0 +1,231 @@
+use std::rc::Rc;
+
+use crate::bigint::{BigInt, MAX_BITS};
+use crate::compiler::Function;
+use crate::complex::Complex;
+use crate::decimal::Decimal;
+use crate::natives::Native;
+use crate::rational::Rational;
+use crate::units::Quantity;
+
+/// A value computed by a program.
+///
+/// Integers are `Int` while they fit in 64 bits and silently become `BigInt`
+/// when they outgrow it, and back again; scripts only ever see one integer
+/// type. In exact mode dividing integers gives a `Rational`, in decimal mode
+/// a `Decimal`. Mixing any of these with a Float promotes it to Float, and
+/// any number with a `Complex` promotes it to Complex. A `Quantity` is a
+/// Float with a unit; arithmetic on quantities checks their dimensions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    BigInt(Rc<BigInt>), // never a value that would fit an Int
+    Rational(Rc<Rational>), // exact mode only; never a whole number
+    Decimal(Rc<Decimal>),
+    Float(f64),
+    Complex(Complex), // never with an imaginary part of exactly zero
+    Quantity(Rc<Quantity>), // never dimensionless
+    Bool(bool),
+    Unit,
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+}
+
+/// Why a program stopped while it was running.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeError {
+    DivisionByZero,
+    Overflow, // an integer result beyond MAX_BITS
+    NegativeExponent, // integer base raised to a negative integer power
+    StackUnderflow,
+    InvalidOpcode(String),
+    UndefinedVariable(String),
+    NoSuchResult(String), // `_`, `ans` or `_N` with no matching history entry
+    TypeError(String),
+    NegativeShift,
+    NotCallable(String), // the type name of what was called
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    RecursionLimit(usize),
+    InvalidArgument(String),
+    Dimension(String), // units that do not fit together, as in `1 m + 1 s`
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeError::DivisionByZero => write!(f, "Division by zero"),
+            RuntimeError::Overflow => write!(f, "Integer overflow: the result would exceed {} bits", MAX_BITS),
+            RuntimeError::NegativeExponent => {
+                write!(f, "Negative exponent for an integer power (use a float base, e.g. 2.0^-1)")
+            },
+            RuntimeError::StackUnderflow => write!(f, "Stack underflow"),
+            RuntimeError::InvalidOpcode(op) => write!(f, "Invalid opcode: {}", op),
+            RuntimeError::UndefinedVariable(name) => write!(f, "Undefined variable: {}", name),
+            RuntimeError::NoSuchResult(name) => write!(f, "No result for {}", name),
+            RuntimeError::TypeError(msg) => write!(f, "Type error: {}", msg),
+            RuntimeError::NegativeShift => write!(f, "Negative shift amount"),
+            RuntimeError::NotCallable(t) => write!(f, "{} is not callable", t),
+            RuntimeError::ArityMismatch { name, expected, found } => {
+                write!(f, "{} takes {} argument(s) but {} were given", name, expected, found)
+            },
+            RuntimeError::RecursionLimit(n) => write!(f, "Recursion depth limit of {} exceeded", n),
+            RuntimeError::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
+            RuntimeError::Dimension(msg) => write!(f, "Dimension error: {}", msg),
+        }
+    }
+}
+
+impl Value {
+    /// The name error messages use for the value's type.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => "Int",
+            Value::Rational(_) => "Rational",
+            Value::Decimal(_) => "Decimal",
+            Value::Float(_) => "Float",
+            Value::Complex(_) => "Complex",
+            Value::Quantity(_) => "Quantity",
+            Value::Bool(_) => "Bool",
+            Value::Unit => "Unit",
+            Value::Function(_) | Value::Native(_) => "Function",
+        }
+    }
+
+    /// Prints the value on its own line, the way the REPL shows results.
+    pub fn get(&mut self) {
+        match self {
+            Value::Rational(r) => println!("{}", r.mixed()),
+            v => println!("{}", v),
+        }
+    }
+
+    /// The value as a real number; Ints are widened.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::BigInt(i) => Some(i.to_f64()),
+            Value::Rational(r) => Some(r.to_f64()),
+            Value::Decimal(d) => Some(d.to_f64()),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Any number as a `Complex`.
+    pub fn to_complex(&self) -> Option<Complex> {
+        match self {
+            Value::Complex(z) => Some(*z),
+            v => Some(Complex::from(v.as_f64()?)),
+        }
+    }
+
+    /// An integer or decimal as a `Decimal`.
+    pub fn to_decimal(&self) -> Option<Decimal> {
+        match self {
+            Value::Decimal(d) => Some(d.as_ref().clone()),
+            v => Some(Decimal::from(v.to_bigint()?)),
+        }
+    }
+
+    /// An integer of either size as a `BigInt`.
+    pub fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(i) => Some(BigInt::from(*i)),
+            Value::BigInt(i) => Some(i.as_ref().clone()),
+            _ => None,
+        }
+    }
+
+    /// An integer or fraction as a `Rational`.
+    pub fn to_rational(&self) -> Option<Rational> {
+        match self {
+            Value::Rational(r) => Some(r.as_ref().clone()),
+            Value::Decimal(d) => Some(d.to_rational()),
+            v => Some(Rational::from(v.to_bigint()?)),
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::BigInt(i) => write!(f, "{}", i),
+            Value::Rational(r) => write!(f, "{}", r),
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Complex(z) => write!(f, "{}", z),
+            Value::Quantity(q) => write!(f, "{}", q),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Unit => write!(f, "()"),
+            Value::Function(func) => write!(f, "<fn {}/{}>", func.name, func.arity),
+            Value::Native(n) => write!(f, "<builtin {}>", n.name),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        return Value::Int(i);
+    }
+}
+
+// Shrinks back to an Int whenever the value fits.
+impl From<BigInt> for Value {
+    fn from(i: BigInt) -> Value {
+        match i.to_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::BigInt(Rc::new(i)),
+        }
+    }
+}
+
+// Whole numbers become integers again.
+impl From<Rational> for Value {
+    fn from(r: Rational) -> Value {
+        if r.is_integer() {
+            return Value::from(r.numer().clone());
+        }
+        return Value::Rational(Rc::new(r));
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Value {
+        return Value::Float(f);
+    }
+}
+
+// A zero imaginary part leaves a Float.
+impl From<Complex> for Value {
+    fn from(z: Complex) -> Value {
+        if z.im == 0.0 {
+            return Value::Float(z.re);
+        }
+        return Value::Complex(z);
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        return Value::Bool(b);
+    }
+}
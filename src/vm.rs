@@ -0,0 +1,794 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::bigint::{BigInt, MAX_BITS};
+use crate::compiler::{ByteCode, ByteCodes, Function};
+use crate::complex::Complex;
+use crate::decimal::{Context, Decimal, Rounding};
+use crate::natives::{builtins, Native, CONSTANTS};
+use crate::rational::Rational;
+use crate::units::{Quantity, Unit};
+use crate::value::{RuntimeError, Value};
+
+// Called after each instruction when tracing; see `VM::set_trace`.
+type Tracer = Box<dyn FnMut(&ByteCode, &[Value])>;
+
+pub struct VM {
+    b: ByteCodes,
+    pub(crate) stack: Vec<Value>,
+    frames: Vec<Frame>,
+    trace: Option<Tracer>,
+}
+
+// A suspended caller while a function runs. `func` is None for the
+// top-level code the VM was created with.
+pub struct Frame {
+    func: Option<Rc<Function>>,
+    ip: usize,
+    locals: Vec<Option<Value>>,
+}
+
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+// Global variables of a REPL session. They outlive the per-line VM.
+pub struct Env {
+    pub globals: HashMap<String, Value>,
+    pub natives: HashMap<String, Rc<Native>>, // built-in and host functions
+    pub history: Vec<Value>, // every successful result, oldest first
+    pub max_depth: usize, // nested calls allowed before RecursionLimit
+    pub exact: bool, // integer division and negative powers give Rationals
+    pub decimal: bool, // ... or Decimals, and `0.1` is read as a Decimal
+    pub context: Context, // precision and rounding of Decimal results
+}
+
+// `_` and `ans` name the previous result, `_N` the Nth result of the session.
+pub fn is_history_ref(name: &str) -> bool {
+    if name == "_" || name == "ans" {
+        return true;
+    }
+    match name.strip_prefix('_') {
+        Some(n) => !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Env {
+            globals: HashMap::new(),
+            natives: builtins().into_iter().map(|n| (n.name.clone(), n)).collect(),
+            history: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            exact: false,
+            decimal: false,
+            context: Context::default(),
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Result<Value, RuntimeError> {
+        if name == "_" || name == "ans" {
+            return self.history.last().cloned().ok_or(RuntimeError::NoSuchResult(name.to_string()));
+        }
+        if is_history_ref(name) {
+            let n = name[1..].parse::<usize>().unwrap_or(0);
+            return match n.checked_sub(1).and_then(|i| self.history.get(i)) {
+                Some(v) => Ok(v.clone()),
+                None => Err(RuntimeError::NoSuchResult(name.to_string())),
+            };
+        }
+        if let Some(v) = self.globals.get(name) {
+            return Ok(v.clone());
+        }
+        if let Some(n) = self.natives.get(name) {
+            return Ok(Value::Native(n.clone()));
+        }
+        if name == "i" {
+            return Ok(Value::Complex(Complex::I));
+        }
+        if let Some((_, c)) = CONSTANTS.iter().find(|(c, _)| *c == name) {
+            return Ok(Value::Float(*c));
+        }
+        return Err(RuntimeError::UndefinedVariable(name.to_string()));
+    }
+
+    pub fn record(&mut self, v: Value) {
+        self.history.push(v);
+    }
+}
+
+pub fn binop_symbol(op: i32) -> &'static str {
+    match op {
+        0 => "+",
+        1 => "-",
+        2 => "*",
+        3 => "/",
+        4 => "^",
+        5 => "==",
+        6 => "!=",
+        7 => "<",
+        8 => "<=",
+        9 => ">",
+        10 => ">=",
+        11 => "%",
+        12 => "//",
+        13 => "&",
+        14 => "|",
+        15 => "xor",
+        16 => "<<",
+        17 => ">>",
+        18 => "in",
+        _ => "?",
+    }
+}
+
+// Operands of a binary operator after numeric promotion: Int only meets
+// Int, a BigInt widens an Int to BigInt, a Decimal widens either to
+// Decimal, a Rational widens any of those to Rational, anything involving
+// a Float is computed as Float, and anything involving a Complex as Complex.
+pub enum Operands {
+    Int(i64, i64),
+    Big(BigInt, BigInt),
+    Dec(Decimal, Decimal),
+    Ratio(Rational, Rational),
+    Float(f64, f64),
+    Complex(Complex, Complex),
+}
+
+pub fn promote(a: &Value, b: &Value) -> Option<Operands> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(Operands::Int(*a, *b)),
+        (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => {
+            Some(Operands::Big(a.to_bigint()?, b.to_bigint()?))
+        },
+        (Value::Int(_) | Value::BigInt(_) | Value::Decimal(_), Value::Int(_) | Value::BigInt(_) | Value::Decimal(_)) => {
+            Some(Operands::Dec(a.to_decimal()?, b.to_decimal()?))
+        },
+        (
+            Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) | Value::Rational(_),
+            Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) | Value::Rational(_),
+        ) => Some(Operands::Ratio(a.to_rational()?, b.to_rational()?)),
+        (Value::Complex(_), _) | (_, Value::Complex(_)) => Some(Operands::Complex(a.to_complex()?, b.to_complex()?)),
+        (Value::Float(a), b) => Some(Operands::Float(*a, b.as_f64()?)),
+        (a, Value::Float(b)) => Some(Operands::Float(a.as_f64()?, *b)),
+        _ => None,
+    }
+}
+
+impl VM {
+    pub fn new(b: ByteCodes) -> Self {
+        VM {
+            b,
+            stack: vec![],
+            frames: vec![],
+            trace: None,
+        }
+    }
+
+    /// Calls `f` after each instruction with the instruction and the stack
+    /// it left behind, top last. An instruction that fails is not reported.
+    pub fn set_trace(&mut self, f: impl FnMut(&ByteCode, &[Value]) + 'static) {
+        self.trace = Some(Box::new(f));
+    }
+
+    fn int_binop(op: i32, a: i64, b: i64) -> Result<i64, RuntimeError> {
+        match op {
+            0 => a.checked_add(b).ok_or(RuntimeError::Overflow),
+            1 => a.checked_sub(b).ok_or(RuntimeError::Overflow),
+            2 => a.checked_mul(b).ok_or(RuntimeError::Overflow),
+            3 => {
+                if b == 0 {
+                    return Err(RuntimeError::DivisionByZero);
+                }
+                a.checked_div(b).ok_or(RuntimeError::Overflow)
+            },
+            4 => {
+                if b < 0 {
+                    return Err(RuntimeError::NegativeExponent);
+                }
+                let e = u32::try_from(b).map_err(|_| RuntimeError::Overflow)?;
+                a.checked_pow(e).ok_or(RuntimeError::Overflow)
+            },
+            11 | 12 => {
+                if b == 0 {
+                    return Err(RuntimeError::DivisionByZero);
+                }
+                // Floor semantics: the remainder takes the sign of the divisor
+                // so that a == (a // b) * b + a % b.
+                let q = a.checked_div(b).ok_or(RuntimeError::Overflow)?;
+                let r = a.wrapping_rem(b);
+                let adjust = r != 0 && (r < 0) != (b < 0);
+                if op == 11 {
+                    return Ok(if adjust { r + b } else { r });
+                }
+                Ok(if adjust { q - 1 } else { q })
+            },
+            13 => Ok(a & b),
+            14 => Ok(a | b),
+            15 => Ok(a ^ b),
+            16 => {
+                if b < 0 {
+                    return Err(RuntimeError::NegativeShift);
+                }
+                if a == 0 {
+                    return Ok(0);
+                }
+                if b >= 64 || (a << b) >> b != a {
+                    return Err(RuntimeError::Overflow);
+                }
+                Ok(a << b)
+            },
+            17 => {
+                if b < 0 {
+                    return Err(RuntimeError::NegativeShift);
+                }
+                Ok(a >> b.min(63))
+            },
+            _ => Err(RuntimeError::InvalidOpcode(format!("BINOP {}", op))),
+        }
+    }
+
+    // Only reached once an i64 overflowed or an operand already is a BigInt.
+    fn big_binop(op: i32, a: &BigInt, b: &BigInt) -> Result<Value, RuntimeError> {
+        let r = match op {
+            0 => a + b,
+            1 => a - b,
+            2 => a * b,
+            3 | 11 | 12 if b.is_zero() => {
+                return Err(RuntimeError::DivisionByZero);
+            },
+            3 => a.div_rem(b).0,
+            4 => {
+                if b.is_negative() {
+                    return Err(RuntimeError::NegativeExponent);
+                }
+                if a.bits() <= 1 {
+                    // 0, 1 and -1 stay small whatever the exponent
+                    let even = (b & &BigInt::from(1)).is_zero();
+                    let r = if b.is_zero() || (a.is_negative() && even) { BigInt::from(1) } else { a.clone() };
+                    return Ok(Value::from(r));
+                }
+                match b.to_i64().and_then(|e| u32::try_from(e).ok()) {
+                    Some(e) if (a.bits() - 1) * e as u64 <= MAX_BITS => a.pow(e),
+                    _ => {
+                        return Err(RuntimeError::Overflow);
+                    }
+                }
+            },
+            11 => a.div_mod_floor(b).1,
+            12 => a.div_mod_floor(b).0,
+            13 => a & b,
+            14 => a | b,
+            15 => a ^ b,
+            16 | 17 if b.is_negative() => {
+                return Err(RuntimeError::NegativeShift);
+            },
+            16 => match b.to_i64() {
+                _ if a.is_zero() => BigInt::default(),
+                Some(n) if a.bits() + n as u64 <= MAX_BITS => a << n as usize,
+                _ => {
+                    return Err(RuntimeError::Overflow);
+                }
+            },
+            17 => a >> b.to_i64().map_or(usize::MAX, |n| n as usize).min(a.bits() as usize + 1),
+            _ => {
+                return Err(RuntimeError::InvalidOpcode(format!("BINOP {}", op)));
+            }
+        };
+        if r.bits() > MAX_BITS {
+            return Err(RuntimeError::Overflow);
+        }
+        return Ok(Value::from(r));
+    }
+
+    fn ratio_binop(op: i32, a: &Rational, b: &Rational) -> Result<Value, RuntimeError> {
+        let r = match op {
+            0 => a + b,
+            1 => a - b,
+            2 => a * b,
+            3 => a * &b.recip().ok_or(RuntimeError::DivisionByZero)?,
+            4 if !b.is_integer() => {
+                return Ok(VM::real_pow(a.to_f64(), b.to_f64()));
+            },
+            4 => match b.numer().to_i64() {
+                Some(e) if a.bits().saturating_sub(1).saturating_mul(e.unsigned_abs()) <= MAX_BITS
+                    && e.unsigned_abs() <= u32::MAX as u64 => {
+                    a.pow(e).ok_or(RuntimeError::DivisionByZero)?
+                },
+                _ if a.numer().is_zero() && b.numer().is_negative() => {
+                    return Err(RuntimeError::DivisionByZero);
+                },
+                _ if a.bits() <= 1 && a.is_integer() => {
+                    // 0, 1 and -1 are their own reciprocals, so only the parity matters
+                    return VM::big_binop(op, a.numer(), &b.numer().abs());
+                },
+                _ => {
+                    return Err(RuntimeError::Overflow);
+                }
+            },
+            11 | 12 => {
+                let q = (a * &b.recip().ok_or(RuntimeError::DivisionByZero)?).floor();
+                if op == 12 {
+                    return Ok(Value::from(q));
+                }
+                a - &(b * &Rational::from(q))
+            },
+            _ => {
+                return Err(RuntimeError::InvalidOpcode(format!("BINOP {}", op)));
+            }
+        };
+        if r.bits() > MAX_BITS {
+            return Err(RuntimeError::Overflow);
+        }
+        return Ok(Value::from(r));
+    }
+
+    // Every result is rounded to `ctx`; one whose scale is out of range
+    // overflows.
+    fn dec_binop(op: i32, a: &Decimal, b: &Decimal, ctx: &Context) -> Result<Value, RuntimeError> {
+        let r = match op {
+            0 => a.add(b, ctx),
+            1 => a.sub(b, ctx),
+            2 => a.mul(b, ctx),
+            3 => a.div(b, ctx).ok_or(RuntimeError::DivisionByZero)?,
+            4 => {
+                let e = b.to_integer(Rounding::Down);
+                // By value: `2.0d` is a whole exponent too
+                if b.cmp(&Decimal::from(e.clone())) != std::cmp::Ordering::Equal {
+                    return Ok(VM::real_pow(a.to_f64(), b.to_f64()));
+                }
+                match e.to_i64().and_then(|e| a.pow(e, ctx)) {
+                    Some(r) => r,
+                    None if a.is_zero() && e.is_negative() => {
+                        return Err(RuntimeError::DivisionByZero);
+                    },
+                    None => {
+                        return Err(RuntimeError::Overflow);
+                    }
+                }
+            },
+            11 | 12 => {
+                if b.is_zero() {
+                    return Err(RuntimeError::DivisionByZero);
+                }
+                let q = (&a.to_rational() * &b.to_rational().recip().unwrap()).floor();
+                if q.bits() > MAX_BITS {
+                    return Err(RuntimeError::Overflow);
+                }
+                let q = Decimal::from(q);
+                if op == 12 {
+                    q
+                } else {
+                    a.sub(&b.mul(&q, ctx), ctx)
+                }
+            },
+            _ => {
+                return Err(RuntimeError::InvalidOpcode(format!("BINOP {}", op)));
+            }
+        };
+        if !r.in_range() {
+            return Err(RuntimeError::Overflow);
+        }
+        return Ok(Value::Decimal(Rc::new(r)));
+    }
+
+    // The session modes change what `/` and negative powers of integers
+    // give: an exact fraction, or a decimal rounded to the context, instead
+    // of truncating or failing.
+    fn session_binop(env: &Env, op: i32, a: &Value, b: &Value) -> Result<Value, RuntimeError> {
+        match (a.to_bigint(), b.to_bigint()) {
+            (Some(x), Some(y)) if env.exact && (op == 3 || op == 4) => {
+                VM::ratio_binop(op, &Rational::from(x), &Rational::from(y))
+            },
+            (Some(x), Some(y)) if env.decimal && (op == 3 || (op == 4 && y.is_negative())) => {
+                VM::dec_binop(op, &Decimal::from(x), &Decimal::from(y), &env.context)
+            },
+            _ => VM::binop(op, a, b, &env.context),
+        }
+    }
+
+    // A negative base to a fractional power has no real value; that gives
+    // the principal complex one rather than NaN.
+    fn real_pow(a: f64, b: f64) -> Value {
+        if a < 0.0 && b.is_finite() && b.fract() != 0.0 {
+            return Value::from(Complex::from(a).pow(Complex::from(b)));
+        }
+        return Value::Float(a.powf(b));
+    }
+
+    fn complex_binop(op: i32, a: Complex, b: Complex) -> Result<Value, RuntimeError> {
+        match op {
+            0 => Ok(Value::from(a + b)),
+            1 => Ok(Value::from(a - b)),
+            2 => Ok(Value::from(a * b)),
+            3 => Ok(Value::from(a / b)),
+            4 => Ok(Value::from(a.pow(b))),
+            _ => Err(RuntimeError::InvalidOpcode(format!("BINOP {}", op))),
+        }
+    }
+
+    // Either operand has a unit; plain real numbers count as dimensionless.
+    // Sums and comparisons need equal dimensions, and a sum keeps the unit
+    // of its left operand. Products whose units cancel become Floats.
+    fn quantity_binop(op: i32, a: &Value, b: &Value) -> Result<Value, RuntimeError> {
+        let quantity = |v: &Value| match v {
+            Value::Quantity(q) => Some(q.as_ref().clone()),
+            Value::Complex(_) => None,
+            v => Some(Quantity { value: v.as_f64()?, unit: Unit::none() }),
+        };
+        let (Some(x), Some(y)) = (quantity(a), quantity(b)) else {
+            return Err(VM::type_error(op, a, b));
+        };
+        let describe = |q: &Quantity| if q.unit.is_dimensionless() { "a number".to_string() } else { q.unit.to_string() };
+        let mismatch = || {
+            RuntimeError::Dimension(format!(
+                "'{}' needs the same dimension on both sides, found {} and {}",
+                binop_symbol(op),
+                describe(&x),
+                describe(&y)
+            ))
+        };
+        match op {
+            0 | 1 => {
+                let y = y.to(&x.unit).ok_or_else(mismatch)?;
+                let value = if op == 0 { x.value + y.value } else { x.value - y.value };
+                Ok(VM::quantity(Quantity { value, unit: x.unit }))
+            },
+            2 | 3 => {
+                let (value, unit) = match op {
+                    2 => (x.value * y.value, x.unit.mul(&y.unit)),
+                    _ => (x.value / y.value, x.unit.div(&y.unit)),
+                };
+                let unit = unit.ok_or_else(|| {
+                    RuntimeError::Dimension(format!("unit power out of range in {} {} {}", x.unit, binop_symbol(op), y.unit))
+                })?;
+                let q = Quantity { value, unit };
+                match q.unit.derived() {
+                    Some(named) => Ok(VM::quantity(q.to(&named).unwrap())),
+                    None => Ok(VM::quantity(q)),
+                }
+            },
+            4 => {
+                if !y.unit.is_dimensionless() {
+                    return Err(RuntimeError::Dimension(format!("'^' needs a plain number as the exponent, found {}", y.unit)));
+                }
+                let unit = x.unit.powf(y.value).ok_or_else(|| {
+                    if y.value.fract() == 0.0 {
+                        return RuntimeError::Dimension(format!("unit power out of range in {} ^ {}", x.unit, y.value));
+                    }
+                    return RuntimeError::Dimension(format!("{} to the power {} is not a unit", x.unit, y.value));
+                })?;
+                Ok(VM::quantity(Quantity { value: x.value.powf(y.value), unit }))
+            },
+            5..=10 => {
+                let y = y.to(&x.unit).ok_or_else(mismatch)?;
+                VM::compare(op, &Value::Float(x.value), &Value::Float(y.value))
+            },
+            18 => match x.to(&y.unit) {
+                Some(q) if !x.unit.is_dimensionless() => Ok(Value::Quantity(Rc::new(q))),
+                _ => Err(RuntimeError::Dimension(format!("cannot convert {} to {}", describe(&x), y.unit))),
+            },
+            _ => Err(VM::type_error(op, a, b)),
+        }
+    }
+
+    fn quantity(q: Quantity) -> Value {
+        if q.unit.is_dimensionless() {
+            return Value::Float(q.si());
+        }
+        return Value::Quantity(Rc::new(q));
+    }
+
+    fn float_binop(op: i32, a: f64, b: f64) -> Result<f64, RuntimeError> {
+        match op {
+            0 => Ok(a + b),
+            1 => Ok(a - b),
+            2 => Ok(a * b),
+            3 => Ok(a / b),
+            4 => Ok(a.powf(b)),
+            _ => Err(RuntimeError::InvalidOpcode(format!("BINOP {}", op))),
+        }
+    }
+
+    pub fn compare(op: i32, a: &Value, b: &Value) -> Result<Value, RuntimeError> {
+        if matches!(a, Value::Quantity(_)) || matches!(b, Value::Quantity(_)) {
+            return VM::quantity_binop(op, a, b);
+        }
+        let ord = match (promote(a, b), a, b) {
+            (Some(Operands::Int(a, b)), _, _) => a.partial_cmp(&b),
+            (Some(Operands::Big(a, b)), _, _) => a.partial_cmp(&b),
+            (Some(Operands::Dec(a, b)), _, _) => a.partial_cmp(&b),
+            (Some(Operands::Ratio(a, b)), _, _) => a.partial_cmp(&b),
+            (Some(Operands::Float(a, b)), _, _) => a.partial_cmp(&b),
+            // Complex numbers are not ordered, only equal or not
+            (Some(Operands::Complex(a, b)), _, _) if op == 5 || op == 6 => (a == b).then_some(std::cmp::Ordering::Equal),
+            (None, Value::Bool(a), Value::Bool(b)) if op == 5 || op == 6 => a.partial_cmp(b),
+            _ => {
+                return Err(VM::type_error(op, a, b));
+            }
+        };
+        // `ord` is None only for NaN, which is unequal to everything.
+        let r = match op {
+            5 => ord == Some(std::cmp::Ordering::Equal),
+            6 => ord != Some(std::cmp::Ordering::Equal),
+            7 => ord.is_some_and(|o| o.is_lt()),
+            8 => ord.is_some_and(|o| o.is_le()),
+            9 => ord.is_some_and(|o| o.is_gt()),
+            10 => ord.is_some_and(|o| o.is_ge()),
+            _ => {
+                return Err(RuntimeError::InvalidOpcode(format!("BINOP {}", op)));
+            }
+        };
+        return Ok(Value::Bool(r));
+    }
+
+    fn type_error(op: i32, a: &Value, b: &Value) -> RuntimeError {
+        return RuntimeError::TypeError(format!(
+            "'{}' is not defined for {} and {}",
+            binop_symbol(op),
+            a.type_name(),
+            b.type_name()
+        ));
+    }
+
+    fn needs_int(op: i32, a: &Value, b: &Value) -> RuntimeError {
+        return RuntimeError::TypeError(format!(
+            "'{}' needs Int operands, found {} and {}",
+            binop_symbol(op),
+            a.type_name(),
+            b.type_name()
+        ));
+    }
+
+    fn binop(op: i32, a: &Value, b: &Value, ctx: &Context) -> Result<Value, RuntimeError> {
+        if (5..=10).contains(&op) {
+            return VM::compare(op, a, b);
+        }
+        if op == 18 || matches!(a, Value::Quantity(_)) || matches!(b, Value::Quantity(_)) {
+            return VM::quantity_binop(op, a, b);
+        }
+        match promote(a, b) {
+            Some(Operands::Int(a, b)) => match VM::int_binop(op, a, b) {
+                Err(RuntimeError::Overflow) => VM::big_binop(op, &BigInt::from(a), &BigInt::from(b)),
+                r => Ok(Value::Int(r?)),
+            },
+            Some(Operands::Big(a, b)) => VM::big_binop(op, &a, &b),
+            // %, // and the bitwise operators only make sense for integers,
+            // though the first two extend naturally to fractions
+            Some(Operands::Float(..)) if (11..=17).contains(&op) => Err(VM::needs_int(op, a, b)),
+            Some(Operands::Dec(..) | Operands::Ratio(..)) if (13..=17).contains(&op) => Err(VM::needs_int(op, a, b)),
+            Some(Operands::Dec(a, b)) => VM::dec_binop(op, &a, &b, ctx),
+            Some(Operands::Ratio(a, b)) => VM::ratio_binop(op, &a, &b),
+            Some(Operands::Float(a, b)) if op == 4 => Ok(VM::real_pow(a, b)),
+            Some(Operands::Float(a, b)) => Ok(Value::Float(VM::float_binop(op, a, b)?)),
+            Some(Operands::Complex(..)) if (11..=17).contains(&op) => Err(VM::type_error(op, a, b)),
+            Some(Operands::Complex(a, b)) => VM::complex_binop(op, a, b),
+            None => Err(VM::type_error(op, a, b)),
+        }
+    }
+
+    fn unaryop(op: i32, a: &Value) -> Result<Value, RuntimeError> {
+        match (op, a) {
+            (0, Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Decimal(_) | Value::Float(_) | Value::Complex(_) | Value::Quantity(_)) => {
+                Ok(a.clone())
+            },
+            (1, Value::Int(a)) => Ok(match a.checked_neg() {
+                Some(n) => Value::Int(n),
+                None => Value::from(-&BigInt::from(*a)),
+            }),
+            (1, Value::BigInt(a)) => Ok(Value::from(-a.as_ref())),
+            (1, Value::Rational(a)) => Ok(Value::from(-a.as_ref())),
+            (1, Value::Decimal(a)) => Ok(Value::Decimal(Rc::new(-a.as_ref()))),
+            (1, Value::Float(a)) => Ok(Value::Float(-a)),
+            (1, Value::Complex(a)) => Ok(Value::Complex(-*a)),
+            (1, Value::Quantity(q)) => Ok(Value::Quantity(Rc::new(Quantity { value: -q.value, unit: q.unit.clone() }))),
+            (2, Value::Bool(b)) => Ok(Value::Bool(!b)),
+            (3, Value::Int(a)) => Ok(Value::Int(!a)),
+            (3, Value::BigInt(a)) => Ok(Value::from(!a.as_ref())),
+            (0..=3, _) => {
+                let sym = ["+", "-", "!", "~"][op as usize];
+                Err(RuntimeError::TypeError(format!("unary '{}' is not defined for {}", sym, a.type_name())))
+            },
+            _ => Err(RuntimeError::InvalidOpcode(format!("UNARYOP {}", op))),
+        }
+    }
+
+    fn condition(v: Option<Value>) -> Result<bool, RuntimeError> {
+        match v {
+            Some(Value::Bool(b)) => Ok(b),
+            Some(v) => Err(RuntimeError::TypeError(format!("expected a Bool condition, found {}", v.type_name()))),
+            None => Err(RuntimeError::StackUnderflow),
+        }
+    }
+
+    pub fn run(&mut self, env: &mut Env) -> Result<Value, RuntimeError> {
+        let stack = &mut self.stack;
+        let frames = &mut self.frames;
+        let main = &self.b.codes;
+        let trace = &mut self.trace;
+
+        // State of the running function; callers wait in `frames`.
+        let mut func: Option<Rc<Function>> = None;
+        let mut locals: Vec<Option<Value>> = Vec::new();
+        let mut ip = 0;
+        frames.clear();
+        // Reported at the top of the loop, as some instructions `continue`.
+        let mut executed: Option<ByteCode> = None;
+
+        loop {
+            if let (Some(t), Some(bc)) = (trace.as_mut(), executed.take()) {
+                t(&bc, stack);
+            }
+            let codes = match &func {
+                Some(f) => &f.code.codes,
+                None => main,
+            };
+            if ip >= codes.len() {
+                break;
+            }
+            let bc = &codes[ip];
+            ip += 1;
+            if trace.is_some() {
+                executed = Some(bc.clone());
+            }
+
+            match bc {
+                ByteCode::LOAD_LOCAL(slot) => match locals.get(*slot) {
+                    Some(Some(v)) => stack.push(v.clone()),
+                    Some(None) => {
+                        let name = func.as_ref().map(|f| f.locals[*slot].clone()).unwrap_or_default();
+                        return Err(RuntimeError::UndefinedVariable(name));
+                    },
+                    None => {
+                        return Err(RuntimeError::InvalidOpcode(format!("LOAD_LOCAL {}", slot)));
+                    }
+                },
+                ByteCode::STORE_LOCAL(slot) => {
+                    let v = stack.last().ok_or(RuntimeError::StackUnderflow)?;
+                    match locals.get_mut(*slot) {
+                        Some(l) => *l = Some(v.clone()),
+                        None => {
+                            return Err(RuntimeError::InvalidOpcode(format!("STORE_LOCAL {}", slot)));
+                        }
+                    }
+                },
+                ByteCode::PUSHFN(f) => {
+                    stack.push(Value::Function(f.clone()));
+                },
+                ByteCode::CALL(argc) => {
+                    let argc = *argc;
+                    let base = stack.len().checked_sub(argc + 1).ok_or(RuntimeError::StackUnderflow)?;
+                    let callee = match &stack[base] {
+                        Value::Function(f) => f.clone(),
+                        Value::Native(n) => {
+                            let n = n.clone();
+                            let args: Vec<Value> = stack.drain(base + 1..).collect();
+                            *stack.last_mut().unwrap() = n.call(args)?;
+                            continue;
+                        },
+                        v => {
+                            return Err(RuntimeError::NotCallable(v.type_name().to_string()));
+                        }
+                    };
+                    if callee.arity != argc {
+                        return Err(RuntimeError::ArityMismatch {
+                            name: callee.name.clone(),
+                            expected: callee.arity,
+                            found: argc,
+                        });
+                    }
+                    if frames.len() >= env.max_depth {
+                        return Err(RuntimeError::RecursionLimit(env.max_depth));
+                    }
+
+                    let mut args: Vec<Option<Value>> = stack.drain(base + 1..).map(Some).collect();
+                    args.resize(callee.locals.len(), None);
+                    stack.pop();
+                    frames.push(Frame {
+                        func: func.take(),
+                        ip,
+                        locals: std::mem::replace(&mut locals, args),
+                    });
+                    func = Some(callee);
+                    ip = 0;
+                },
+                ByteCode::RET => {
+                    let frame = frames.pop().ok_or(RuntimeError::InvalidOpcode("RET".to_string()))?;
+                    func = frame.func;
+                    ip = frame.ip;
+                    locals = frame.locals;
+                },
+                ByteCode::LOAD(name) => {
+                    stack.push(env.lookup(name)?);
+                },
+                ByteCode::STORE(name) => {
+                    let v = stack.last().ok_or(RuntimeError::StackUnderflow)?;
+                    env.globals.insert(name.clone(), v.clone());
+                },
+                ByteCode::PUSHI(i) => {
+                    stack.push(Value::Int(*i));
+                },
+                ByteCode::PUSHBIG(i) => {
+                    stack.push(Value::BigInt(i.clone()));
+                },
+                ByteCode::PUSHDEC(d) => {
+                    stack.push(Value::Decimal(d.clone()));
+                },
+                ByteCode::PUSHF(f) => {
+                    stack.push(Value::Float(*f));
+                },
+                ByteCode::PUSHIM(f) => {
+                    stack.push(Value::from(Complex::new(0.0, *f)));
+                },
+                ByteCode::PUSHQ(q) => {
+                    stack.push(Value::Quantity(q.clone()));
+                },
+                ByteCode::PUSHB(b) => {
+                    stack.push(Value::Bool(*b));
+                },
+                ByteCode::PUSHU => {
+                    stack.push(Value::Unit);
+                },
+                ByteCode::POP => {
+                    stack.pop().ok_or(RuntimeError::StackUnderflow)?;
+                },
+                ByteCode::FOR_ITER(t) => {
+                    let n = stack.len();
+                    if n < 2 {
+                        return Err(RuntimeError::StackUnderflow);
+                    }
+                    match (&stack[n - 2], &stack[n - 1]) {
+                        (Value::Int(i), Value::Int(end)) => {
+                            let i = *i;
+                            if i < *end {
+                                stack[n - 2] = Value::Int(i + 1);
+                                stack.push(Value::Int(i));
+                            } else {
+                                ip = *t;
+                            }
+                        },
+                        (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => {
+                            if VM::compare(7, &stack[n - 2], &stack[n - 1])? == Value::Bool(true) {
+                                let i = stack[n - 2].clone();
+                                stack[n - 2] = VM::binop(0, &i, &Value::Int(1), &env.context)?;
+                                stack.push(i);
+                            } else {
+                                ip = *t;
+                            }
+                        },
+                        (a, b) => {
+                            return Err(RuntimeError::TypeError(format!(
+                                "for loop range needs Int bounds, found {} and {}",
+                                a.type_name(),
+                                b.type_name()
+                            )));
+                        }
+                    }
+                },
+                ByteCode::JUMP(t) => {
+                    ip = *t;
+                },
+                ByteCode::JUMP_IF_FALSE(t) => {
+                    if !VM::condition(stack.pop())? {
+                        ip = *t;
+                    }
+                },
+                ByteCode::JUMP_IF_TRUE(t) => {
+                    if VM::condition(stack.pop())? {
+                        ip = *t;
+                    }
+                },
+                ByteCode::BINOP(op) => {
+                    let b = stack.pop().ok_or(RuntimeError::StackUnderflow)?;
+                    let a = stack.last_mut().ok_or(RuntimeError::StackUnderflow)?;
+                    *a = VM::session_binop(env, *op, a, &b)?;
+                },
+                ByteCode::UNARYOP(op) => {
+                    let a = stack.last_mut().ok_or(RuntimeError::StackUnderflow)?;
+                    *a = VM::unaryop(*op, a)?;
+                }
+            }
+        }
+
+        return stack.pop().ok_or(RuntimeError::StackUnderflow);
+    }
+}
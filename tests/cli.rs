@@ -0,0 +1,152 @@
+#![allow(clippy::needless_return)]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn mds(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mds"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    return child.wait_with_output().unwrap();
+}
+
+fn stdout(out: &Output) -> String {
+    return String::from_utf8_lossy(&out.stdout).into_owned();
+}
+
+fn stderr(out: &Output) -> String {
+    return String::from_utf8_lossy(&out.stderr).into_owned();
+}
+
+// A script file unique to the test, removed again when the test ends.
+struct Script(std::path::PathBuf);
+
+impl Script {
+    fn new(name: &str, source: &str) -> Script {
+        let path = std::env::temp_dir().join(format!("mds-cli-{}-{}.mds", std::process::id(), name));
+        std::fs::write(&path, source).unwrap();
+        return Script(path);
+    }
+
+    fn path(&self) -> &str {
+        return self.0.to_str().unwrap();
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn expression_argument() {
+    let out = mds(&["-e", "2^10"], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "1024\n");
+
+    let out = mds(&["-e", "x = 1"], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "1\n");
+
+    let out = mds(&["-e", "fn f(x) = x"], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "");
+}
+
+#[test]
+fn script_file() {
+    let script = Script::new("ok", "x = 2\ny = x ^ 3\ny + 1\n");
+    let out = mds(&[script.path()], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "9\n");
+
+    let out = mds(&["/nonexistent/script.mds"], "");
+    assert_eq!(out.status.code(), Some(66));
+    assert!(stderr(&out).starts_with("mds: cannot read /nonexistent/script.mds"));
+}
+
+#[test]
+fn program_from_stdin() {
+    let out = mds(&["-"], "fn sq(x) = x * x\nsq(12)\n");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "144\n");
+}
+
+#[test]
+fn syntax_errors_exit_with_65() {
+    let out = mds(&["-e", "1 +"], "");
+    assert_eq!(out.status.code(), Some(65));
+    assert_eq!(stdout(&out), "");
+    assert!(stderr(&out).starts_with("error: Expected atom, found end of input"));
+
+    let script = Script::new("syntax", "x = 1\nx x\n");
+    let out = mds(&[script.path()], "");
+    assert_eq!(out.status.code(), Some(65));
+    assert!(stderr(&out).contains(" --> 2:3"));
+
+    let out = mds(&["-"], &format!("{}1{}", "(".repeat(5000), ")".repeat(5000)));
+    assert_eq!(out.status.code(), Some(65));
+    assert!(stderr(&out).contains("nested too deeply"));
+
+    let out = mds(&["-"], &format!("{}1", "false ? 0 : ".repeat(100_000)));
+    assert_eq!(out.status.code(), Some(65));
+    assert!(stderr(&out).contains("nested too deeply"));
+}
+
+#[test]
+fn runtime_errors_exit_with_70() {
+    let out = mds(&["-e", "1/0"], "");
+    assert_eq!(out.status.code(), Some(70));
+    assert_eq!(stderr(&out), "Error: Division by zero\n");
+
+    let out = mds(&["-"], "x = 1\ny\n");
+    assert_eq!(out.status.code(), Some(70));
+    assert_eq!(stderr(&out), "Error: Undefined variable: y\n");
+}
+
+#[test]
+fn usage_errors_exit_with_64() {
+    for args in [&["-x"][..], &["-e"], &["a.mds", "b.mds"], &["-e", "1", "2"]] {
+        let out = mds(args, "");
+        assert_eq!(out.status.code(), Some(64), "{:?}", args);
+        assert!(stderr(&out).starts_with("Usage: mds"));
+    }
+
+    let out = mds(&["--help"], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert!(stdout(&out).starts_with("Usage: mds"));
+}
+
+#[test]
+fn long_generated_programs() {
+    let script = Script::new("long", &vec!["1"; 200_000].join("+"));
+    let out = mds(&[script.path()], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "200000\n");
+
+    let out = mds(&["-"], &vec!["1"; 200_000].join("+"));
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "200000\n");
+}
+
+#[test]
+fn interactive_session_on_a_pipe() {
+    // Without a terminal there is no prompt; unclosed brackets continue
+    // onto the next line, and input left unclosed at the end still runs.
+    let out = mds(&[], "x = (1 +\n2)\nx * 2\n{ x\n");
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = stdout(&out);
+    assert!(stdout.starts_with("3\n6\nerror: Expected ';', a new line or '}', found end of input"), "{}", stdout);
+}
+
+#[test]
+fn settings_out_of_range_are_refused() {
+    let out = mds(&[], ":set precision 4000000000\n:set precision 3\n1d / 3\n");
+    assert_eq!(stdout(&out), "Expected a number of digits from 1 to 100000, found '4000000000'\n0.333\n");
+}